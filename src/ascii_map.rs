@@ -0,0 +1,393 @@
+//! Levels drawn as text.
+//!
+//! Instead of `walls`, a level can have a multiline `map`:
+//!
+//! ```toml
+//! map = """
+//! ##==####
+//! #..1...#
+//! #.####.|
+//! #@...2.|
+//! ########
+//! """
+//!
+//! [legend.1]
+//! effect = "ActivateDoors"
+//! light_radius = 48.0
+//!
+//! [legend.2]
+//! entity_type = "Shard3"
+//! effect = "IncreasePlayerLightRadius"
+//! light_radius = 32.0
+//! ```
+//!
+//! `#` is a solid wall, `.` is empty, runs of 2 or 3 `=` (horizontal) or
+//! `|` (vertical) are doors, `@` is the player start and digits are
+//! entities described by the `legend` table. The level's size and walls
+//! are taken from the map, so `width` and `height` are ignored, and so is
+//! the player start if there's a `@`.
+use crate::resources::{DoorPart, Entity, EntityType, Level, PickUpEffect, Wall};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LegendEntry {
+	#[serde(default = "LegendEntry::default_entity_type")]
+	pub entity_type: EntityType,
+	pub effect: PickUpEffect,
+	pub light_radius: f32,
+}
+
+impl LegendEntry {
+	fn default_entity_type() -> EntityType {
+		EntityType::Shard0
+	}
+
+	fn from_entity(entity: &Entity) -> Self {
+		Self {
+			entity_type: entity.entity_type.clone(),
+			effect: entity.effect.clone(),
+			light_radius: entity.light_radius,
+		}
+	}
+}
+
+/// Replaces `level.map` with the walls, entities and player start it
+/// describes. Entities from the map are added after the listed ones.
+pub fn apply(level: &mut Level) -> Result<(), String> {
+	let map = match level.map.take() {
+		Some(map) => map,
+		None => return Ok(()),
+	};
+
+	if !level.walls.is_empty() {
+		return Err("Level can't have both walls and a map!".to_owned());
+	}
+
+	let rows: Vec<Vec<char>> = map.lines()
+		.map(|line| line.trim_end().chars().collect())
+		.filter(|row: &Vec<char>| !row.is_empty())
+		.collect();
+
+	let height = rows.len();
+	let width = rows.first().map_or(0, |row| row.len());
+
+	if width == 0 {
+		return Err("Map is empty!".to_owned());
+	}
+
+	if let Some(y) = rows.iter().position(|row| row.len() != width) {
+		return Err(format!("Map row {} is {} tiles long, expected {}!", y, rows[y].len(), width));
+	}
+
+	level.width = width;
+	level.height = height;
+	level.walls = vec![Wall::N; width * height];
+
+	let mut player = None;
+
+	for (y, row) in rows.iter().enumerate() {
+		for (x, &c) in row.iter().enumerate() {
+			match c {
+				'.' | '=' | '|' => {},
+				'#' => level.set(x, y, Wall::S),
+				'@' => {
+					if player.is_some() {
+						return Err(format!("Second player start at {}, {}!", x, y));
+					}
+
+					player = Some((x, y));
+				},
+				'0'..='9' => {
+					let entry = level.legend.get(&c.to_string())
+						.ok_or_else(|| format!("Entity {} at {}, {} isn't in the legend!", c, x, y))?;
+
+					level.entities.push(Entity {
+						entity_type: entry.entity_type.clone(),
+						x: x as f32 + 0.5,
+						y: y as f32 + 0.5,
+						light_radius: entry.light_radius,
+						effect: entry.effect.clone(),
+					});
+				},
+				_ => return Err(format!("Unknown map tile {:?} at {}, {}!", c, x, y)),
+			}
+		}
+	}
+
+	// horizontal doors
+	for (y, row) in rows.iter().enumerate() {
+		let runs = find_runs(row.iter().cloned(), '=');
+
+		for (start, length) in runs {
+			place_door(level, length, true, |index| (start + index, y))?;
+		}
+	}
+
+	// vertical doors
+	for x in 0..width {
+		let runs = find_runs(rows.iter().map(|row| row[x]), '|');
+
+		for (start, length) in runs {
+			place_door(level, length, false, |index| (x, start + index))?;
+		}
+	}
+
+	if let Some((x, y)) = player {
+		level.player_x = x as f32 + 0.5;
+		level.player_y = y as f32 + 0.5;
+	}
+
+	level.legend.clear();
+	level.uses_map = true;
+
+	Ok(())
+}
+
+/// Turns a level back into a map. Anything that doesn't fit in there
+/// (off-centre entities, more than ten kinds of them) stays listed.
+pub fn export(level: &Level) -> Result<Level, String> {
+	let mut rows: Vec<Vec<char>> = vec![vec!['.'; level.width]; level.height];
+
+	for y in 0..level.height {
+		for x in 0..level.width {
+			let wall = level.get(x, y);
+
+			rows[y][x] = match wall.door_part() {
+				Some(part) if part.horizontal => '=',
+				Some(_) => '|',
+				None if wall.is_wall() => '#',
+				None => '.',
+			};
+		}
+	}
+
+	let mut exported = level.clone();
+	exported.entities.clear();
+
+	let tile_of = |x: f32, y: f32| -> Option<(usize, usize)> {
+		let is_centered = x >= 0.0 && y >= 0.0 && x.fract() == 0.5 && y.fract() == 0.5;
+		let (tile_x, tile_y) = (x as usize, y as usize);
+
+		if is_centered && tile_x < level.width && tile_y < level.height && level.get(tile_x, tile_y).is_empty() {
+			Some((tile_x, tile_y))
+		}
+		else {
+			None
+		}
+	};
+
+	if let Some((x, y)) = tile_of(level.player_x, level.player_y) {
+		rows[y][x] = '@';
+	}
+
+	let mut legend: Vec<LegendEntry> = Vec::new();
+
+	for entity in level.entities.iter() {
+		let entry = LegendEntry::from_entity(entity);
+		let digit = match legend.iter().position(|other| *other == entry) {
+			Some(digit) => Some(digit),
+			None if legend.len() < 10 => {
+				legend.push(entry);
+				Some(legend.len() - 1)
+			},
+			None => None,
+		};
+
+		match (tile_of(entity.x, entity.y), digit) {
+			(Some((x, y)), Some(digit)) if rows[y][x] == '.' => {
+				rows[y][x] = std::char::from_digit(digit as u32, 10).unwrap();
+			},
+			_ => exported.entities.push(entity.clone()),
+		}
+	}
+
+	let map: Vec<String> = rows.iter()
+		.map(|row| row.iter().collect())
+		.collect();
+
+	exported.map = Some(format!("\n{}\n", map.join("\n")));
+	exported.legend = legend.into_iter()
+		.enumerate()
+		.map(|(digit, entry)| (digit.to_string(), entry))
+		.collect::<BTreeMap<_, _>>();
+	exported.walls.clear();
+
+	// doors right next to each other would be read back as a single one
+	let mut check = exported.clone();
+	check.entities.clear();
+	apply(&mut check)?;
+
+	if check.walls != level.walls {
+		return Err("Level has doors which can't be told apart in a map!".to_owned());
+	}
+
+	Ok(exported)
+}
+
+/// Exports the level and serializes it, pretty printing keeps the map
+/// multiline.
+pub fn to_toml(level: &Level) -> Result<String, String> {
+	let exported = export(level)?;
+
+	toml::to_string_pretty(&exported)
+		.map_err(|e| e.to_string())
+}
+
+/// `--export-map <level.toml> <output.toml>`
+/// Returns `false` if the level couldn't be exported.
+pub fn run_command(args: &[String]) -> bool {
+	if args.len() < 2 {
+		println!("usage: --export-map <level.toml> <output.toml>");
+		return false;
+	}
+
+	let result = fs::read_to_string(&args[0])
+		.map_err(|e| e.to_string())
+		.and_then(|content| Level::parse(&content).map_err(|e| e.to_string()))
+		.and_then(|level| to_toml(&level))
+		.and_then(|content| fs::write(&args[1], content).map_err(|e| e.to_string()));
+
+	match result {
+		Ok(()) => {
+			println!("Exported {} into {}.", args[0], args[1]);
+			true
+		},
+		Err(e) => {
+			println!("{}: {}", args[0], e);
+			false
+		},
+	}
+}
+
+/// Returns `(start, length)` of every run of `c`.
+fn find_runs<I: Iterator<Item = char>>(tiles: I, c: char) -> Vec<(usize, usize)> {
+	let mut runs = Vec::new();
+	let mut start = None;
+	let mut n = 0;
+
+	for (i, tile) in tiles.enumerate() {
+		if tile == c {
+			if start.is_none() {
+				start = Some(i);
+			}
+		}
+		else if let Some(s) = start.take() {
+			runs.push((s, i - s));
+		}
+
+		n = i + 1;
+	}
+
+	if let Some(s) = start {
+		runs.push((s, n - s));
+	}
+
+	runs
+}
+
+fn place_door<F: Fn(usize) -> (usize, usize)>(level: &mut Level, length: usize, horizontal: bool, position: F) -> Result<(), String> {
+	for index in 0..length {
+		let (x, y) = position(index);
+
+		let wall = Wall::from_door_part(DoorPart { index, length, horizontal })
+			.ok_or_else(|| format!("Door at {}, {} is {} tiles long, only 2 or 3 are possible!", x, y, length))?;
+
+		level.set(x, y, wall);
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The example from the module documentation.
+	const LEVEL: &str = r#"
+key = "test"
+player_light_radius = 64.0
+shards_for_door_activation = 1
+
+map = """
+##==####
+#..1...#
+#.####.|
+#@...2.|
+########
+"""
+
+[legend.1]
+effect = "ActivateDoors"
+light_radius = 48.0
+
+[legend.2]
+entity_type = "Shard3"
+effect = "IncreasePlayerLightRadius"
+light_radius = 32.0
+"#;
+
+	fn apply_map(map: &str) -> Result<Level, String> {
+		let mut level = Level::new(0, 0, "test");
+		level.map = Some(map.to_owned());
+		level.legend.insert("1".to_owned(), LegendEntry {
+			entity_type: EntityType::Shard0,
+			effect: PickUpEffect::ActivateDoors,
+			light_radius: 48.0,
+		});
+
+		apply(&mut level).map(|()| level)
+	}
+
+	#[test]
+	fn documented_map() {
+		let level = Level::parse(LEVEL).unwrap();
+
+		assert_eq!((level.width, level.height), (8, 5));
+		assert_eq!((level.player_x, level.player_y), (1.5, 3.5));
+		assert_eq!(level.get(2, 0), Wall::B0H);
+		assert_eq!(level.get(3, 0), Wall::B1H);
+		assert_eq!(level.get(7, 2), Wall::B0V);
+		assert_eq!(level.get(7, 3), Wall::B1V);
+		assert_eq!(level.get(1, 1), Wall::N);
+		assert_eq!(level.get(2, 2), Wall::S);
+
+		let entities: Vec<_> = level.entities.iter()
+			.map(|entity| (entity.entity_type.clone(), entity.x, entity.y, entity.effect.clone()))
+			.collect();
+
+		assert_eq!(entities, vec![
+			(EntityType::Shard0, 3.5, 1.5, PickUpEffect::ActivateDoors),
+			(EntityType::Shard3, 5.5, 3.5, PickUpEffect::IncreasePlayerLightRadius),
+		]);
+	}
+
+	#[test]
+	fn exported_map_reads_back_the_same() {
+		let level = Level::parse(LEVEL).unwrap();
+		let exported = Level::parse(&to_toml(&level).unwrap()).unwrap();
+
+		assert_eq!(toml::to_string(&exported).unwrap(), toml::to_string(&level).unwrap());
+	}
+
+	#[test]
+	fn rows_must_have_the_same_length() {
+		assert_eq!(apply_map("####\n#@.\n####").unwrap_err(), "Map row 1 is 3 tiles long, expected 4!");
+	}
+
+	#[test]
+	fn unknown_tile() {
+		assert_eq!(apply_map("###\n#?#\n###").unwrap_err(), "Unknown map tile '?' at 1, 1!");
+	}
+
+	#[test]
+	fn entity_missing_from_legend() {
+		assert_eq!(apply_map("####\n#1.#\n#.2#\n####").unwrap_err(), "Entity 2 at 2, 2 isn't in the legend!");
+	}
+
+	#[test]
+	fn door_too_long() {
+		assert_eq!(apply_map("#====#\n#@...#").unwrap_err(), "Door at 1, 0 is 4 tiles long, only 2 or 3 are possible!");
+	}
+}
@@ -0,0 +1,270 @@
+//! Wall shapes from the level grid.
+//!
+//! Every wall gets a mask of its open neighbours, which tells which of its
+//! faces and inner corners can be seen at all. Combined with the faces the
+//! light actually reached, it decides how the wall is drawn, so the result
+//! doesn't depend on the exact angles of the rays.
+use crate::lighting::TileLightState;
+use crate::resources::Level;
+
+// Faces, or orthogonal neighbours.
+pub const UP: u8 = 1 << 0;
+pub const RIGHT: u8 = 1 << 1;
+pub const DOWN: u8 = 1 << 2;
+pub const LEFT: u8 = 1 << 3;
+
+// Corners, or diagonal neighbours.
+pub const UP_LEFT: u8 = 1 << 4;
+pub const UP_RIGHT: u8 = 1 << 5;
+pub const DOWN_RIGHT: u8 = 1 << 6;
+pub const DOWN_LEFT: u8 = 1 << 7;
+
+pub const FACES: u8 = UP | RIGHT | DOWN | LEFT;
+pub const CORNERS: u8 = UP_LEFT | UP_RIGHT | DOWN_RIGHT | DOWN_LEFT;
+pub const ALL: u8 = FACES | CORNERS;
+
+/// Mask of the neighbours of `x`, `y` that nothing stands in, activated
+/// doors included. Everything outside of the level counts as solid.
+pub fn open_neighbors(level: &Level, x: usize, y: usize, are_doors_activated: bool) -> u8 {
+	let neighbors = [
+		(UP, 0, -1),
+		(RIGHT, 1, 0),
+		(DOWN, 0, 1),
+		(LEFT, -1, 0),
+		(UP_LEFT, -1, -1),
+		(UP_RIGHT, 1, -1),
+		(DOWN_RIGHT, 1, 1),
+		(DOWN_LEFT, -1, 1),
+	];
+
+	let mut mask = 0;
+
+	for &(bit, dx, dy) in neighbors.iter() {
+		let nx = x as isize + dx;
+		let ny = y as isize + dy;
+
+		if nx < 0 || ny < 0 || nx >= level.width as isize || ny >= level.height as isize {
+			continue;
+		}
+
+		let wall = level.get(nx as usize, ny as usize);
+
+		if wall.is_empty() || (wall.is_door() && are_doors_activated) {
+			mask |= bit;
+		}
+	}
+
+	mask
+}
+
+/// Faces and inner corners which can be seen from the open neighbours.
+/// An inner corner is only visible where both faces next to it are
+/// covered by walls, otherwise it's just a part of a face.
+pub fn exposed(open: u8) -> u8 {
+	let faces = open & FACES;
+	let mut corners = 0;
+
+	let corner_faces = [
+		(UP_LEFT, UP | LEFT),
+		(UP_RIGHT, UP | RIGHT),
+		(DOWN_RIGHT, DOWN | RIGHT),
+		(DOWN_LEFT, DOWN | LEFT),
+	];
+
+	for &(corner, next_faces) in corner_faces.iter() {
+		if open & corner != 0 && faces & next_faces == 0 {
+			corners |= corner;
+		}
+	}
+
+	faces | corners
+}
+
+/// Picks the shapes a wall is drawn with from its open neighbours and the
+/// faces and corners reached by light (same bits as the neighbour mask).
+/// Adjacent lit faces are joined into big corners, a wall lit all around
+/// is `Full`, and every lit inner corner is drawn on top of the faces.
+pub fn light_shapes(open: u8, lit: u8) -> Vec<TileLightState> {
+	use TileLightState::*;
+
+	let visible = exposed(open) & lit;
+	let faces = visible & FACES;
+	let mut shapes = Vec::new();
+
+	if faces == FACES {
+		shapes.push(Full);
+	}
+	else {
+		let big_corners = [
+			(UpRightBig, UP | RIGHT),
+			(DownRightBig, DOWN | RIGHT),
+			(DownLeftBig, DOWN | LEFT),
+			(UpLeftBig, UP | LEFT),
+		];
+
+		let mut covered = 0;
+
+		for &(shape, pair) in big_corners.iter() {
+			if faces & pair == pair && pair & !covered != 0 {
+				shapes.push(shape);
+				covered |= pair;
+			}
+		}
+
+		let sides = [(Up, UP), (Right, RIGHT), (Down, DOWN), (Left, LEFT)];
+
+		for &(shape, face) in sides.iter() {
+			if faces & face != 0 && covered & face == 0 {
+				shapes.push(shape);
+			}
+		}
+	}
+
+	let small_corners = [
+		(UpLeftSmall, UP_LEFT),
+		(UpRightSmall, UP_RIGHT),
+		(DownRightSmall, DOWN_RIGHT),
+		(DownLeftSmall, DOWN_LEFT),
+	];
+
+	for &(shape, corner) in small_corners.iter() {
+		if visible & corner != 0 {
+			shapes.push(shape);
+		}
+	}
+
+	shapes
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::resources::Wall;
+	use TileLightState::*;
+
+	/// `#` is a wall, anything else is empty.
+	fn level(rows: &[&str]) -> Level {
+		let mut level = Level::new(rows[0].len(), rows.len(), "test");
+
+		for (y, row) in rows.iter().enumerate() {
+			for (x, c) in row.chars().enumerate() {
+				if c == '#' {
+					level.set(x, y, Wall::S);
+				}
+			}
+		}
+
+		level
+	}
+
+	/// Shapes of the wall at `x`, `y` lit from everywhere.
+	fn shapes(level: &Level, x: usize, y: usize, are_doors_activated: bool) -> Vec<TileLightState> {
+		light_shapes(open_neighbors(level, x, y, are_doors_activated), ALL)
+	}
+
+	#[test]
+	fn pillar_is_full() {
+		let level = level(&[
+			"...",
+			".#.",
+			"...",
+		]);
+
+		assert_eq!(open_neighbors(&level, 1, 1, false), ALL);
+		assert_eq!(shapes(&level, 1, 1, false), vec![Full]);
+	}
+
+	#[test]
+	fn straight_wall_lights_both_faces() {
+		let level = level(&[
+			".....",
+			".###.",
+			".....",
+		]);
+
+		assert_eq!(shapes(&level, 2, 1, false), vec![Up, Down]);
+	}
+
+	#[test]
+	fn end_caps_join_three_faces() {
+		let level = level(&[
+			".....",
+			".###.",
+			".....",
+		]);
+
+		assert_eq!(shapes(&level, 1, 1, false), vec![DownLeftBig, UpLeftBig]);
+		assert_eq!(shapes(&level, 3, 1, false), vec![UpRightBig, DownRightBig]);
+	}
+
+	#[test]
+	fn t_junction_keeps_inner_corners() {
+		let level = level(&[
+			".....",
+			".###.",
+			"..#..",
+			".....",
+		]);
+
+		assert_eq!(shapes(&level, 2, 1, false), vec![Up, DownRightSmall, DownLeftSmall]);
+	}
+
+	#[test]
+	fn cross_lights_all_inner_corners() {
+		let level = level(&[
+			".....",
+			"..#..",
+			".###.",
+			"..#..",
+			".....",
+		]);
+
+		assert_eq!(shapes(&level, 2, 2, false), vec![UpLeftSmall, UpRightSmall, DownRightSmall, DownLeftSmall]);
+	}
+
+	#[test]
+	fn inner_corner_is_kept_next_to_lit_faces() {
+		let level = level(&[
+			"....",
+			".##.",
+			".#..",
+			"....",
+		]);
+		let open = open_neighbors(&level, 1, 1, false);
+
+		assert_eq!(light_shapes(open, ALL), vec![UpLeftBig, DownRightSmall]);
+		assert_eq!(light_shapes(open, UP | DOWN_RIGHT), vec![Up, DownRightSmall]);
+		assert_eq!(light_shapes(open, DOWN_RIGHT), vec![DownRightSmall]);
+	}
+
+	#[test]
+	fn unlit_or_hidden_faces_are_not_drawn() {
+		let level = level(&[
+			"...",
+			".##",
+			"...",
+		]);
+		let open = open_neighbors(&level, 1, 1, false);
+
+		assert_eq!(light_shapes(open, 0), vec![]);
+		// the right face is covered by the other wall
+		assert_eq!(light_shapes(open, RIGHT), vec![]);
+	}
+
+	#[test]
+	fn activated_doors_count_as_open() {
+		let mut level = level(&[
+			".....",
+			"##..#",
+			".....",
+		]);
+		level.set(2, 1, Wall::B0H);
+		level.set(3, 1, Wall::B1H);
+
+		assert_eq!(open_neighbors(&level, 1, 1, false) & RIGHT, 0);
+		assert_eq!(shapes(&level, 1, 1, false), vec![Up, Down]);
+
+		assert_eq!(open_neighbors(&level, 1, 1, true) & RIGHT, RIGHT);
+		assert_eq!(shapes(&level, 1, 1, true), vec![UpRightBig, DownRightBig]);
+	}
+}
@@ -0,0 +1,318 @@
+//! Boxes swept through the level grid.
+//!
+//! Every solid tile the moving box could touch on its way is tested,
+//! however far it moves, so nothing tunnels through walls. Boxes only
+//! collide when their insides overlap, touching edges is fine, which is
+//! what lets them slide along walls.
+use crate::resources::{Level, Wall};
+use crate::types::{Rect, Vector2};
+use std::ops::Range;
+
+/// Distance a box is kept from the tile it hit, so rounding never leaves
+/// it inside of one.
+pub const EPSILON: f32 = 0.01;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+	/// Fraction of the movement done before touching the tile, 0 to 1.
+	pub time: f32,
+	/// Points out of the face that was hit.
+	pub normal: Vector2,
+	/// `x`, `y` of the tile.
+	pub tile: (usize, usize),
+}
+
+/// Whether the insides of the boxes overlap.
+pub fn overlaps(a: Rect, b: Rect) -> bool {
+	a.x < b.x + b.w && b.x < a.x + a.w && a.y < b.y + b.h && b.y < a.y + a.h
+}
+
+/// Tiles of the level the inside of `object` overlaps.
+pub fn overlapping_tiles(level: &Level, tile_size: f32, object: Rect) -> Vec<(usize, usize)> {
+	let columns = tile_range(object.x, object.x + object.w, tile_size, level.width);
+	let rows = tile_range(object.y, object.y + object.h, tile_size, level.height);
+
+	rows.flat_map(|y| columns.clone().map(move |x| (x, y)))
+		.collect()
+}
+
+/// First tile `is_solid` accepts that `object` runs into while moving by
+/// `movement`. Tiles it already overlaps are ignored, so boxes stuck in a
+/// wall can still get out.
+pub fn sweep<F: Fn(&Wall) -> bool>(level: &Level, tile_size: f32, object: Rect, movement: Vector2, is_solid: F) -> Option<Hit> {
+	let swept = Rect::new(
+		object.x + movement.x.min(0.0),
+		object.y + movement.y.min(0.0),
+		object.w + movement.x.abs(),
+		object.h + movement.y.abs(),
+	);
+
+	let mut first: Option<Hit> = None;
+
+	for (x, y) in overlapping_tiles(level, tile_size, swept) {
+		if !is_solid(&level.get(x, y)) {
+			continue;
+		}
+
+		let tile = Rect::new(x as f32 * tile_size, y as f32 * tile_size, tile_size, tile_size);
+
+		if let Some((time, normal)) = sweep_box(object, movement, tile) {
+			if first.map_or(true, |hit| time < hit.time) {
+				first = Some(Hit {
+					time,
+					normal,
+					tile: (x, y),
+				});
+			}
+		}
+	}
+
+	first
+}
+
+/// Time and normal of `object` moving by `movement` running into `other`.
+fn sweep_box(object: Rect, movement: Vector2, other: Rect) -> Option<(f32, Vector2)> {
+	let (x_entry, x_exit) = axis_times(object.x, object.x + object.w, movement.x, other.x, other.x + other.w)?;
+	let (y_entry, y_exit) = axis_times(object.y, object.y + object.h, movement.y, other.y, other.y + other.h)?;
+
+	let entry = x_entry.max(y_entry);
+	let exit = x_exit.min(y_exit);
+
+	if entry >= exit || entry < 0.0 || entry > 1.0 {
+		return None;
+	}
+
+	let normal = if x_entry > y_entry {
+		Vector2::new(-movement.x.signum(), 0.0)
+	}
+	else {
+		Vector2::new(0.0, -movement.y.signum())
+	};
+
+	Some((entry, normal))
+}
+
+/// Times at which the boxes start and stop overlapping on one axis,
+/// `None` if they never do.
+fn axis_times(min: f32, max: f32, velocity: f32, other_min: f32, other_max: f32) -> Option<(f32, f32)> {
+	if velocity > 0.0 {
+		Some(((other_min - max) / velocity, (other_max - min) / velocity))
+	}
+	else if velocity < 0.0 {
+		Some(((other_max - min) / velocity, (other_min - max) / velocity))
+	}
+	else if max > other_min && min < other_max {
+		Some((std::f32::NEG_INFINITY, std::f32::INFINITY))
+	}
+	else {
+		None
+	}
+}
+
+/// Tiles from `start` to `end` on one axis, without the ones only touched.
+fn tile_range(start: f32, end: f32, tile_size: f32, count: usize) -> Range<usize> {
+	let first = (start / tile_size).floor().max(0.0);
+	let last = (end / tile_size).ceil().min(count as f32);
+
+	if last > first {
+		first as usize..last as usize
+	}
+	else {
+		0..0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::rngs::StdRng;
+	use rand::{Rng, SeedableRng};
+
+	const TILE_SIZE: f32 = 32.0;
+	/// Slack for float rounding when checking positions.
+	const TOLERANCE: f32 = 1e-3;
+
+	fn solid(wall: &Wall) -> bool {
+		!wall.is_empty()
+	}
+
+	fn moved(object: Rect, movement: Vector2, time: f32) -> Rect {
+		Rect::new(object.x + movement.x * time, object.y + movement.y * time, object.w, object.h)
+	}
+
+	/// `object` shrunk by `TOLERANCE` on every side.
+	fn inner(object: Rect) -> Rect {
+		Rect::new(object.x + TOLERANCE, object.y + TOLERANCE, object.w - 2.0 * TOLERANCE, object.h - 2.0 * TOLERANCE)
+	}
+
+	fn hits_solid(level: &Level, object: Rect) -> bool {
+		overlapping_tiles(level, TILE_SIZE, inner(object)).into_iter()
+			.any(|(x, y)| solid(&level.get(x, y)))
+	}
+
+	fn random_level(rng: &mut StdRng) -> Level {
+		let width = rng.gen_range(3, 24);
+		let height = rng.gen_range(3, 24);
+		let mut level = Level::new(width, height, "test");
+
+		for y in 0..height {
+			for x in 0..width {
+				if rng.gen_bool(0.3) {
+					level.set(x, y, Wall::S);
+				}
+			}
+		}
+
+		level
+	}
+
+	/// A box of random size somewhere in or around the level that doesn't
+	/// overlap any walls.
+	fn random_box(rng: &mut StdRng, level: &Level) -> Option<Rect> {
+		for _ in 0..100 {
+			let w = rng.gen_range(1.0, 2.0 * TILE_SIZE);
+			let h = rng.gen_range(1.0, 2.0 * TILE_SIZE);
+			let x = rng.gen_range(-w, level.width as f32 * TILE_SIZE);
+			let y = rng.gen_range(-h, level.height as f32 * TILE_SIZE);
+			let object = Rect::new(x, y, w, h);
+
+			if !hits_solid(level, object) {
+				return Some(object);
+			}
+		}
+
+		None
+	}
+
+	#[test]
+	fn swept_boxes_never_enter_walls() {
+		let mut rng = StdRng::seed_from_u64(22);
+		let mut hits = 0;
+
+		for _ in 0..2000 {
+			let level = random_level(&mut rng);
+			let object = match random_box(&mut rng, &level) {
+				Some(object) => object,
+				None => continue,
+			};
+
+			// up to four tiles per tick
+			let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+			let speed = rng.gen_range(0.0, 4.0 * TILE_SIZE);
+			let movement = Vector2::new(angle.cos(), angle.sin()) * speed;
+
+			let hit = sweep(&level, TILE_SIZE, object, movement, solid);
+			let end = hit.map_or(1.0, |hit| hit.time);
+
+			for step in 0..=256 {
+				let time = end * step as f32 / 256.0;
+
+				assert!(
+					!hits_solid(&level, moved(object, movement, time)),
+					"{:?} moving by {:?} is inside a wall at {}, hit {:?}", object, movement, time, hit,
+				);
+			}
+
+			if let Some(hit) = hit {
+				hits += 1;
+
+				assert!(hit.time >= 0.0 && hit.time <= 1.0, "{:?}", hit);
+				assert!(hit.normal.dot(movement) < 0.0, "{:?} doesn't oppose {:?}", hit, movement);
+				assert_eq!(hit.normal.x.abs() + hit.normal.y.abs(), 1.0, "{:?}", hit);
+				assert!(solid(&level.get(hit.tile.0, hit.tile.1)));
+
+				// stopped right at the face of the tile that was hit
+				let stop = moved(object, movement, hit.time);
+				let (tile_x, tile_y) = (hit.tile.0 as f32 * TILE_SIZE, hit.tile.1 as f32 * TILE_SIZE);
+				let gap = if hit.normal.x > 0.0 {
+					stop.x - (tile_x + TILE_SIZE)
+				}
+				else if hit.normal.x < 0.0 {
+					tile_x - (stop.x + stop.w)
+				}
+				else if hit.normal.y > 0.0 {
+					stop.y - (tile_y + TILE_SIZE)
+				}
+				else {
+					tile_y - (stop.y + stop.h)
+				};
+
+				assert!(gap.abs() < TOLERANCE * 10.0, "{:?} stopped {} away from the tile", hit, gap);
+			}
+		}
+
+		// make sure walls were actually in the way most of the time
+		assert!(hits > 500, "only {} hits", hits);
+	}
+
+	#[test]
+	fn touching_boxes_do_not_overlap() {
+		let a = Rect::new(0.0, 0.0, 32.0, 32.0);
+
+		assert!(!overlaps(a, Rect::new(32.0, 0.0, 32.0, 32.0)));
+		assert!(!overlaps(a, Rect::new(0.0, -32.0, 32.0, 32.0)));
+		assert!(overlaps(a, Rect::new(31.0, 31.0, 32.0, 32.0)));
+	}
+
+	#[test]
+	fn axis_times_without_velocity() {
+		let infinite = Some((std::f32::NEG_INFINITY, std::f32::INFINITY));
+
+		assert_eq!(axis_times(0.0, 10.0, 0.0, 5.0, 15.0), infinite);
+		// touching, or apart, never overlaps
+		assert_eq!(axis_times(0.0, 10.0, 0.0, 10.0, 20.0), None);
+		assert_eq!(axis_times(0.0, 10.0, 0.0, 20.0, 30.0), None);
+	}
+
+	#[test]
+	fn axis_times_with_velocity() {
+		assert_eq!(axis_times(0.0, 10.0, 10.0, 20.0, 30.0), Some((1.0, 3.0)));
+		assert_eq!(axis_times(20.0, 30.0, -10.0, 0.0, 10.0), Some((1.0, 3.0)));
+		// already touching starts overlapping right away
+		assert_eq!(axis_times(0.0, 10.0, 5.0, 10.0, 20.0), Some((0.0, 4.0)));
+		// moving away it happened in the past
+		assert_eq!(axis_times(0.0, 10.0, -5.0, 10.0, 20.0), Some((-4.0, 0.0)));
+	}
+
+	#[test]
+	fn tile_range_skips_touched_tiles() {
+		assert_eq!(tile_range(0.0, 32.0, TILE_SIZE, 10), 0..1);
+		assert_eq!(tile_range(32.0, 64.0, TILE_SIZE, 10), 1..2);
+		assert_eq!(tile_range(31.0, 65.0, TILE_SIZE, 10), 0..3);
+	}
+
+	#[test]
+	fn tile_range_outside_grid() {
+		assert_eq!(tile_range(-64.0, -32.0, TILE_SIZE, 10), 0..0);
+		assert_eq!(tile_range(-16.0, 0.0, TILE_SIZE, 10), 0..0);
+		assert_eq!(tile_range(320.0, 400.0, TILE_SIZE, 10), 0..0);
+		assert_eq!(tile_range(-16.0, 16.0, TILE_SIZE, 10), 0..1);
+		assert_eq!(tile_range(300.0, 400.0, TILE_SIZE, 10), 9..10);
+	}
+
+	#[test]
+	fn sweep_edge_cases() {
+		let mut level = Level::new(3, 3, "test");
+		level.set(1, 1, Wall::S);
+
+		// touching the wall's left face
+		let object = Rect::new(16.0, 40.0, 16.0, 16.0);
+
+		let hit = sweep(&level, TILE_SIZE, object, Vector2::new(8.0, 0.0), solid).unwrap();
+		assert_eq!(hit.time, 0.0);
+		assert_eq!(hit.normal, Vector2::new(-1.0, 0.0));
+		assert_eq!(hit.tile, (1, 1));
+
+		assert_eq!(sweep(&level, TILE_SIZE, object, Vector2::new(-8.0, 0.0), solid), None);
+		assert_eq!(sweep(&level, TILE_SIZE, object, Vector2::new(0.0, 8.0), solid), None);
+		assert_eq!(sweep(&level, TILE_SIZE, object, Vector2::zero(), solid), None);
+
+		// outside of the grid everything is open
+		let outside = Rect::new(-64.0, 40.0, 16.0, 16.0);
+		assert_eq!(sweep(&level, TILE_SIZE, outside, Vector2::new(0.0, 200.0), solid), None);
+
+		// boxes stuck in a wall can get out
+		let stuck = Rect::new(40.0, 40.0, 16.0, 16.0);
+		assert_eq!(sweep(&level, TILE_SIZE, stuck, Vector2::new(64.0, 0.0), solid), None);
+	}
+}
@@ -0,0 +1,465 @@
+//! Procedurally generated labyrinths.
+//!
+//! Mazes are carved on a grid of cells at odd tile coordinates, with the
+//! tiles in between being walls or passages. The result is a regular
+//! `Level`, so it can be saved as TOML or played right away.
+use crate::resources::{DoorPart, Entity, EntityType, Level, PickUpEffect, Wall};
+use crate::validation;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::fs;
+use std::path;
+
+const MIN_SIZE: usize = 7;
+const MAX_ROOM_SIZE: usize = 3;
+const ROOM_ATTEMPTS: usize = 64;
+const SHARD_LIGHT_RADIUS: f32 = 48.0;
+/// Seeds are saved in TOML levels, which only have signed integers.
+pub const MAX_SEED: u64 = std::i64::MAX as u64;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Algorithm {
+	RecursiveBacktracker,
+	Prim,
+	RoomsAndCorridors,
+}
+
+impl Algorithm {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"backtracker" | "RecursiveBacktracker" => Some(Self::RecursiveBacktracker),
+			"prim" | "Prim" => Some(Self::Prim),
+			"rooms" | "RoomsAndCorridors" => Some(Self::RoomsAndCorridors),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GeneratorOptions {
+	pub algorithm: Algorithm,
+	pub seed: u64,
+	pub width: usize,
+	pub height: usize,
+	/// Wall tileset, see `TilePack::load`.
+	pub key: String,
+
+	/// Number of `ActivateDoors` shards, all of them are needed.
+	#[serde(default = "GeneratorOptions::default_shards")]
+	pub shards: usize,
+	/// Number of `IncreasePlayerLightRadius` shards.
+	#[serde(default)]
+	pub light_shards: usize,
+	#[serde(default = "GeneratorOptions::default_player_light_radius")]
+	pub player_light_radius: f32,
+}
+
+impl GeneratorOptions {
+	pub fn new(algorithm: Algorithm, seed: u64, width: usize, height: usize, key: &str) -> Self {
+		Self {
+			algorithm,
+			seed,
+			width,
+			height,
+			key: key.to_owned(),
+
+			shards: Self::default_shards(),
+			light_shards: 0,
+			player_light_radius: Self::default_player_light_radius(),
+		}
+	}
+
+	fn default_shards() -> usize {
+		3
+	}
+
+	fn default_player_light_radius() -> f32 {
+		64.0
+	}
+}
+
+type Cell = (usize, usize);
+
+pub fn generate(options: &GeneratorOptions) -> Level {
+	let mut rng = StdRng::seed_from_u64(options.seed);
+
+	let width = odd_size(options.width);
+	let height = odd_size(options.height);
+
+	let mut level = Level::new(width, height, &options.key);
+	level.walls = vec![Wall::S; width * height];
+	level.player_light_radius = options.player_light_radius;
+	level.seed = Some(options.seed);
+
+	match options.algorithm {
+		Algorithm::RecursiveBacktracker => carve_backtracker(&mut level, &mut rng),
+		Algorithm::Prim => carve_prim(&mut level, &mut rng),
+		Algorithm::RoomsAndCorridors => carve_rooms(&mut level, &mut rng),
+	}
+
+	place_door(&mut level, &mut rng);
+	place_player_and_shards(&mut level, options, &mut rng);
+
+	level
+}
+
+/// `--generate-level <algorithm> <seed> <width> <height> <key> <output>`
+/// Returns `false` if the level couldn't be generated.
+pub fn run_command(resource_path: &path::Path, args: &[String]) -> bool {
+	if args.len() < 6 {
+		println!("usage: --generate-level <backtracker|prim|rooms> <seed> <width> <height> <key> <output>");
+		return false;
+	}
+
+	let algorithm = match Algorithm::from_name(&args[0]) {
+		Some(algorithm) => algorithm,
+		None => {
+			println!("Unknown algorithm {}!", args[0]);
+			return false;
+		},
+	};
+
+	let (seed, width, height) = match (parse_seed(&args[1]), args[2].parse(), args[3].parse()) {
+		(Some(seed), Ok(width), Ok(height)) => (seed, width, height),
+		_ => {
+			println!("Seed, width and height have to be numbers, seed at most {}!", MAX_SEED);
+			return false;
+		},
+	};
+
+	let key = &args[4];
+
+	if !resource_path.join("images").join("walls").join(key).is_dir() {
+		println!("There's no wall tileset {}!", key);
+		return false;
+	}
+
+	let level = generate(&GeneratorOptions::new(algorithm, seed, width, height, key));
+
+	for diagnostic in validation::validate(&level) {
+		println!("warning: {}", diagnostic);
+	}
+
+	let content = match toml::to_string(&level) {
+		Ok(content) => content,
+		Err(e) => {
+			println!("Couldn't serialize level: {}", e);
+			return false;
+		},
+	};
+
+	if let Err(e) = fs::write(&args[5], content) {
+		println!("Couldn't write {}: {}", args[5], e);
+		return false;
+	}
+
+	println!("Generated {}x{} level into {}.", level.width, level.height, args[5]);
+
+	true
+}
+
+/// Parses a seed in `0..=MAX_SEED`.
+pub fn parse_seed(arg: &str) -> Option<u64> {
+	arg.parse::<u64>()
+		.ok()
+		.filter(|&seed| seed <= MAX_SEED)
+}
+
+/// Mazes need odd sizes, so there's a wall on each side of every cell.
+fn odd_size(size: usize) -> usize {
+	let size = size.max(MIN_SIZE);
+
+	if size % 2 == 0 {
+		size + 1
+	}
+	else {
+		size
+	}
+}
+
+fn cell_count(level: &Level) -> (usize, usize) {
+	((level.width - 1) / 2, (level.height - 1) / 2)
+}
+
+fn cell_neighbors(cell: Cell, columns: usize, rows: usize) -> Vec<Cell> {
+	let (x, y) = cell;
+	let mut neighbors = Vec::new();
+
+	if x > 0 {
+		neighbors.push((x - 1, y));
+	}
+	if x + 1 < columns {
+		neighbors.push((x + 1, y));
+	}
+	if y > 0 {
+		neighbors.push((x, y - 1));
+	}
+	if y + 1 < rows {
+		neighbors.push((x, y + 1));
+	}
+
+	neighbors
+}
+
+fn carve_cell(level: &mut Level, cell: Cell) {
+	level.set(cell.0 * 2 + 1, cell.1 * 2 + 1, Wall::N);
+}
+
+fn carve_between(level: &mut Level, a: Cell, b: Cell) {
+	carve_cell(level, a);
+	carve_cell(level, b);
+	level.set(a.0 + b.0 + 1, a.1 + b.1 + 1, Wall::N);
+}
+
+fn carve_backtracker(level: &mut Level, rng: &mut StdRng) {
+	let (columns, rows) = cell_count(level);
+	let mut visited = vec![false; columns * rows];
+
+	let start = (rng.gen_range(0, columns), rng.gen_range(0, rows));
+	visited[start.1 * columns + start.0] = true;
+	carve_cell(level, start);
+
+	let mut stack = vec![start];
+
+	while let Some(&current) = stack.last() {
+		let unvisited: Vec<Cell> = cell_neighbors(current, columns, rows)
+			.into_iter()
+			.filter(|&(x, y)| !visited[y * columns + x])
+			.collect();
+
+		if let Some(&next) = unvisited.choose(rng) {
+			visited[next.1 * columns + next.0] = true;
+			carve_between(level, current, next);
+			stack.push(next);
+		}
+		else {
+			stack.pop();
+		}
+	}
+}
+
+fn carve_prim(level: &mut Level, rng: &mut StdRng) {
+	let (columns, rows) = cell_count(level);
+	let mut visited = vec![false; columns * rows];
+
+	let start = (rng.gen_range(0, columns), rng.gen_range(0, rows));
+	visited[start.1 * columns + start.0] = true;
+	carve_cell(level, start);
+
+	// (cell, visited cell it would be connected to)
+	let mut frontier: Vec<(Cell, Cell)> = cell_neighbors(start, columns, rows)
+		.into_iter()
+		.map(|cell| (cell, start))
+		.collect();
+
+	while !frontier.is_empty() {
+		let index = rng.gen_range(0, frontier.len());
+		let (cell, from) = frontier.swap_remove(index);
+
+		if visited[cell.1 * columns + cell.0] {
+			continue;
+		}
+
+		visited[cell.1 * columns + cell.0] = true;
+		carve_between(level, from, cell);
+
+		for neighbor in cell_neighbors(cell, columns, rows) {
+			if !visited[neighbor.1 * columns + neighbor.0] {
+				frontier.push((neighbor, cell));
+			}
+		}
+	}
+}
+
+fn carve_rooms(level: &mut Level, rng: &mut StdRng) {
+	let (columns, rows) = cell_count(level);
+
+	// rooms are (x, y, width, height) in cells
+	let mut rooms: Vec<(usize, usize, usize, usize)> = Vec::new();
+
+	for _ in 0..ROOM_ATTEMPTS {
+		let width = rng.gen_range(1, MAX_ROOM_SIZE.min(columns) + 1);
+		let height = rng.gen_range(1, MAX_ROOM_SIZE.min(rows) + 1);
+		let x = rng.gen_range(0, columns - width + 1);
+		let y = rng.gen_range(0, rows - height + 1);
+
+		let overlaps = rooms.iter().any(|&(rx, ry, rw, rh)| {
+			x < rx + rw && rx < x + width && y < ry + rh && ry < y + height
+		});
+
+		if !overlaps {
+			rooms.push((x, y, width, height));
+		}
+	}
+
+	for &(x, y, width, height) in rooms.iter() {
+		for tile_y in (y * 2 + 1)..(y + height) * 2 {
+			for tile_x in (x * 2 + 1)..(x + width) * 2 {
+				level.set(tile_x, tile_y, Wall::N);
+			}
+		}
+	}
+
+	// connecting every room with the next one keeps them all reachable
+	rooms.sort_by_key(|&(x, y, _, _)| (x, y));
+
+	for pair in rooms.windows(2) {
+		let (ax, ay, aw, ah) = pair[0];
+		let (bx, by, bw, bh) = pair[1];
+
+		let a = ((ax + aw / 2) * 2 + 1, (ay + ah / 2) * 2 + 1);
+		let b = ((bx + bw / 2) * 2 + 1, (by + bh / 2) * 2 + 1);
+
+		for x in a.0.min(b.0)..=a.0.max(b.0) {
+			level.set(x, a.1, Wall::N);
+		}
+		for y in a.1.min(b.1)..=a.1.max(b.1) {
+			level.set(b.0, y, Wall::N);
+		}
+	}
+}
+
+fn empty_tiles(level: &Level) -> Vec<(usize, usize)> {
+	let mut tiles = Vec::new();
+
+	for y in 0..level.height {
+		for x in 0..level.width {
+			if level.get(x, y).is_empty() {
+				tiles.push((x, y));
+			}
+		}
+	}
+
+	tiles
+}
+
+/// Puts a 2 or 3 tile door on the outer wall and carves a corridor from
+/// a random empty tile to it.
+fn place_door(level: &mut Level, rng: &mut StdRng) {
+	let length = rng.gen_range(2, 4);
+	let horizontal: bool = rng.gen();
+	let far_side: bool = rng.gen();
+
+	let (target_x, target_y) = *empty_tiles(level)
+		.choose(rng)
+		.expect("Generated level has no empty tiles!");
+
+	if horizontal {
+		let door_y = if far_side { level.height - 1 } else { 0 };
+		let inner_y = if far_side { level.height - 2 } else { 1 };
+
+		let offset = rng.gen_range(0, length);
+		let start_x = target_x.saturating_sub(offset).max(1).min(level.width - 1 - length);
+
+		for y in inner_y.min(target_y)..=inner_y.max(target_y) {
+			level.set(target_x, y, Wall::N);
+		}
+
+		for index in 0..length {
+			let part = DoorPart { index, length, horizontal };
+
+			level.set(start_x + index, inner_y, Wall::N);
+			level.set(start_x + index, door_y, Wall::from_door_part(part).unwrap());
+		}
+	}
+	else {
+		let door_x = if far_side { level.width - 1 } else { 0 };
+		let inner_x = if far_side { level.width - 2 } else { 1 };
+
+		let offset = rng.gen_range(0, length);
+		let start_y = target_y.saturating_sub(offset).max(1).min(level.height - 1 - length);
+
+		for x in inner_x.min(target_x)..=inner_x.max(target_x) {
+			level.set(x, target_y, Wall::N);
+		}
+
+		for index in 0..length {
+			let part = DoorPart { index, length, horizontal };
+
+			level.set(inner_x, start_y + index, Wall::N);
+			level.set(door_x, start_y + index, Wall::from_door_part(part).unwrap());
+		}
+	}
+}
+
+fn place_player_and_shards(level: &mut Level, options: &GeneratorOptions, rng: &mut StdRng) {
+	let mut tiles = empty_tiles(level);
+	tiles.shuffle(rng);
+
+	let mut tiles = tiles.into_iter();
+
+	if let Some((x, y)) = tiles.next() {
+		level.player_x = x as f32 + 0.5;
+		level.player_y = y as f32 + 0.5;
+	}
+
+	let entity_types = [
+		EntityType::Shard0,
+		EntityType::Shard1,
+		EntityType::Shard2,
+		EntityType::Shard3,
+		EntityType::Shard4,
+	];
+
+	for n in 0..(options.shards + options.light_shards) {
+		let (x, y) = match tiles.next() {
+			Some(tile) => tile,
+			None => break,
+		};
+
+		let effect = if n < options.shards {
+			level.shards_for_door_activation += 1;
+
+			PickUpEffect::ActivateDoors
+		}
+		else {
+			PickUpEffect::IncreasePlayerLightRadius
+		};
+
+		level.entities.push(Entity {
+			entity_type: entity_types.choose(rng).unwrap().clone(),
+			x: x as f32 + 0.5,
+			y: y as f32 + 0.5,
+			light_radius: SHARD_LIGHT_RADIUS,
+			effect,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const ALGORITHMS: [Algorithm; 3] = [Algorithm::RecursiveBacktracker, Algorithm::Prim, Algorithm::RoomsAndCorridors];
+
+	#[test]
+	fn generated_levels_are_valid() {
+		for &algorithm in ALGORITHMS.iter() {
+			for &(width, height) in [(7, 7), (8, 12), (15, 9), (31, 21)].iter() {
+				for seed in 0..20 {
+					let level = generate(&GeneratorOptions::new(algorithm, seed, width, height, "test"));
+					let diagnostics = validation::validate(&level);
+
+					assert!(
+						diagnostics.is_empty(),
+						"{:?} {}x{} with seed {}: {:?}", algorithm, width, height, seed, diagnostics
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn same_seed_same_level() {
+		for &algorithm in ALGORITHMS.iter() {
+			let options = GeneratorOptions::new(algorithm, 45, 21, 15, "test");
+
+			let first = toml::to_string(&generate(&options)).unwrap();
+			let second = toml::to_string(&generate(&options)).unwrap();
+
+			assert_eq!(first, second);
+		}
+	}
+}
@@ -0,0 +1,158 @@
+//! Fastest completion of every level, played back as a ghost.
+//!
+//! Ghosts are kept next to the save as JSON (level hashes don't fit into
+//! TOML integers) and only count for the exact level file they were
+//! recorded in.
+use crate::types::Point2;
+use ggez;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path;
+
+const GHOSTS_FILE: &str = "ghosts.json";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GhostFrame {
+	/// Seconds since the level started.
+	pub time: f32,
+	pub x: f32,
+	pub y: f32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Ghost {
+	/// `Level::content_hash` of the level it was recorded in.
+	pub level_hash: u64,
+	pub time: f32,
+	pub frames: Vec<GhostFrame>,
+}
+
+impl Ghost {
+	/// Player coordinates at `time`, `None` once the run is over.
+	pub fn position(&self, time: f32) -> Option<Point2> {
+		// frames are sorted by time, looking for the first one at or past it
+		let next = match self.frames.binary_search_by(|frame| frame.time.partial_cmp(&time).unwrap_or(Ordering::Less)) {
+			Ok(next) | Err(next) => next,
+		};
+		let to = self.frames.get(next)?;
+
+		if next == 0 {
+			return Some(Point2::new(to.x, to.y));
+		}
+
+		let from = &self.frames[next - 1];
+		let span = to.time - from.time;
+		let t = if span > 0.0 { (time - from.time) / span } else { 1.0 };
+
+		Some(Point2::new(
+			from.x + (to.x - from.x) * t,
+			from.y + (to.y - from.y) * t,
+		))
+	}
+}
+
+/// Keyed by transition name.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Ghosts {
+	pub levels: BTreeMap<String, Ghost>,
+}
+
+impl Ghosts {
+	fn path(context: &ggez::Context) -> path::PathBuf {
+		ggez::filesystem::user_data_dir(context).join(GHOSTS_FILE)
+	}
+
+	/// Missing or broken ghosts are just gone.
+	pub fn load(context: &ggez::Context) -> Self {
+		let path = Self::path(context);
+
+		if !path.exists() {
+			return Self::default();
+		}
+
+		let ghosts = fs::read_to_string(&path)
+			.map_err(|e| e.to_string())
+			.and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()));
+
+		match ghosts {
+			Ok(ghosts) => ghosts,
+			Err(e) => {
+				warn!("Couldn't read ghosts {:?}: {}", path, e);
+
+				Self::default()
+			},
+		}
+	}
+
+	pub fn write(&self, context: &ggez::Context) -> Result<(), String> {
+		let path = Self::path(context);
+
+		let content = serde_json::to_string(self)
+			.map_err(|e| e.to_string())?;
+
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)
+				.map_err(|e| e.to_string())?;
+		}
+
+		fs::write(&path, content)
+			.map_err(|e| e.to_string())
+	}
+
+	/// The ghost of `name`, unless the level changed since it was recorded.
+	pub fn get(&self, name: &str, level_hash: u64) -> Option<&Ghost> {
+		self.levels.get(name)
+			.filter(|ghost| ghost.level_hash == level_hash)
+	}
+
+	/// Keeps `ghost` if it's the fastest one of the current version of the
+	/// level. Returns `false` if it isn't.
+	pub fn offer(&mut self, name: &str, ghost: Ghost) -> bool {
+		let is_faster = self.get(name, ghost.level_hash)
+			.map_or(true, |best| ghost.time < best.time);
+
+		if is_faster {
+			self.levels.insert(name.to_owned(), ghost);
+		}
+
+		is_faster
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ghost() -> Ghost {
+		let frames = (0..5)
+			.map(|n| GhostFrame {
+				time: n as f32,
+				x: n as f32 * 10.0,
+				y: 0.0,
+			})
+			.collect();
+
+		Ghost {
+			level_hash: 0,
+			time: 4.0,
+			frames,
+		}
+	}
+
+	#[test]
+	fn position_interpolates_between_frames() {
+		let ghost = ghost();
+
+		assert_eq!(ghost.position(0.0), Some(Point2::new(0.0, 0.0)));
+		assert_eq!(ghost.position(2.0), Some(Point2::new(20.0, 0.0)));
+		assert_eq!(ghost.position(2.5), Some(Point2::new(25.0, 0.0)));
+		assert_eq!(ghost.position(-1.0), Some(Point2::new(0.0, 0.0)));
+	}
+
+	#[test]
+	fn position_is_gone_after_run() {
+		assert_eq!(ghost().position(4.5), None);
+	}
+}
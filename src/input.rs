@@ -1,11 +1,24 @@
 //! Typedefs for input shortcuts.
 use ggez::event::*;
 use ggez_goodies::input;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq, Hash, Serialize)]
 pub enum Button {
 	Next,
 	Quit,
+	Minimap,
+	Ghost,
+	Continue,
+	QuickSave,
+	QuickLoad,
+
+	// editor
+	NextBrush,
+	Save,
+	Playtest,
+	Decrease,
+	Increase,
 
 	Up,
 	Down,
@@ -13,7 +26,7 @@ pub enum Button {
 	Right,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq, Hash, Serialize)]
 pub enum Axis {
 	Vert,
 	Horz,
@@ -38,4 +51,15 @@ pub fn create_input_binding() -> input::InputBinding<Axis, Button> {
 
 		.bind_key_to_button(KeyCode::Space, Button::Next)
 		.bind_key_to_button(KeyCode::Escape, Button::Quit)
+		.bind_key_to_button(KeyCode::M, Button::Minimap)
+		.bind_key_to_button(KeyCode::G, Button::Ghost)
+		.bind_key_to_button(KeyCode::C, Button::Continue)
+		.bind_key_to_button(KeyCode::F5, Button::QuickSave)
+		.bind_key_to_button(KeyCode::F9, Button::QuickLoad)
+
+		.bind_key_to_button(KeyCode::Tab, Button::NextBrush)
+		.bind_key_to_button(KeyCode::F2, Button::Save)
+		.bind_key_to_button(KeyCode::Return, Button::Playtest)
+		.bind_key_to_button(KeyCode::LBracket, Button::Decrease)
+		.bind_key_to_button(KeyCode::RBracket, Button::Increase)
 }
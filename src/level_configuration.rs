@@ -1,5 +1,7 @@
+use crate::generator;
 use crate::resources::{Level};
-use rand::{thread_rng, Rng};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
 
 pub struct TileConfiguration {
 	pub tile_id: usize,
@@ -8,13 +10,21 @@ pub struct TileConfiguration {
 }
 
 pub struct LevelConfiguration {
+	pub seed: u64,
 	pub tiles: Vec<TileConfiguration>,
 }
 
 impl LevelConfiguration {
-	pub fn new(level: &Level, side_count: usize, corner_count: usize) -> Self {
+	/// `seed` overrides the level's own seed. Without either of them
+	/// a random one is picked (and logged, so it can be reproduced).
+	pub fn new(level: &Level, side_count: usize, corner_count: usize, seed: Option<u64>) -> Self {
+		let seed = seed
+			.or(level.seed)
+			.unwrap_or_else(|| thread_rng().gen_range(0, generator::MAX_SEED));
+		info!("Level configuration seed: {}", seed);
+
 		let mut tiles = Vec::new();
-		let mut rng = thread_rng();
+		let mut rng = StdRng::seed_from_u64(seed);
 
 		for (tile_id, _) in level.walls.iter().enumerate() {
 			tiles.push(TileConfiguration {
@@ -24,7 +34,26 @@ impl LevelConfiguration {
 			});
 		}
 
+		// applied afterwards so pinning a tile doesn't reshuffle all the others,
+		// invalid ones are reported by `validation::validate`
+		for variant in level.tile_variants.iter() {
+			if variant.x >= level.width || variant.y >= level.height {
+				continue;
+			}
+
+			let tile = &mut tiles[level.width * variant.y + variant.x];
+
+			if let Some(side) = variant.side.filter(|&side| side < side_count) {
+				tile.side = side;
+			}
+
+			if let Some(corner) = variant.corner.filter(|&corner| corner < corner_count) {
+				tile.corner = corner;
+			}
+		}
+
 		Self {
+			seed,
 			tiles,
 		}
 	}
@@ -41,3 +70,55 @@ impl LevelConfiguration {
 		self.tiles[tile_id].corner
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::resources::TileVariant;
+
+	const SIDE_COUNT: usize = 8;
+	const CORNER_COUNT: usize = 4;
+
+	fn variants(configuration: &LevelConfiguration) -> Vec<(usize, usize)> {
+		configuration.tiles.iter()
+			.map(|tile| (tile.side, tile.corner))
+			.collect()
+	}
+
+	#[test]
+	fn same_seed_same_tiles() {
+		let mut level = Level::new(10, 10, "test");
+		level.seed = Some(7);
+
+		let first = LevelConfiguration::new(&level, SIDE_COUNT, CORNER_COUNT, None);
+		let second = LevelConfiguration::new(&level, SIDE_COUNT, CORNER_COUNT, None);
+		let overridden = LevelConfiguration::new(&Level::new(10, 10, "test"), SIDE_COUNT, CORNER_COUNT, Some(7));
+
+		assert_eq!(first.seed, 7);
+		assert_eq!(variants(&first), variants(&second));
+		assert_eq!(variants(&first), variants(&overridden));
+	}
+
+	#[test]
+	fn pinned_variant_wins() {
+		let mut level = Level::new(10, 10, "test");
+		let unpinned = variants(&LevelConfiguration::new(&level, SIDE_COUNT, CORNER_COUNT, Some(7)));
+
+		// a side and corner that differ from the random pick
+		let (side, corner) = unpinned[23];
+		let side = (side + 1) % SIDE_COUNT;
+		let corner = (corner + 1) % CORNER_COUNT;
+
+		level.tile_variants.push(TileVariant { x: 3, y: 2, side: Some(side), corner: Some(corner) });
+		// out of range, so ignored
+		level.tile_variants.push(TileVariant { x: 4, y: 2, side: Some(SIDE_COUNT), corner: None });
+
+		let pinned = variants(&LevelConfiguration::new(&level, SIDE_COUNT, CORNER_COUNT, Some(7)));
+
+		assert_eq!(pinned[23], (side, corner));
+
+		for tile_id in (0..unpinned.len()).filter(|&tile_id| tile_id != 23) {
+			assert_eq!(pinned[tile_id], unpinned[tile_id]);
+		}
+	}
+}
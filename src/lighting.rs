@@ -1,124 +1,153 @@
+use crate::autotile;
 use crate::level_configuration::{LevelConfiguration};
 use crate::resources;
 use crate::types::{Point2, Rect};
 use ggez::graphics;
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 
 const SEGMENT_COUNT: usize = 8;
 
-#[derive(Debug)]
-struct SegmentPalette {
-	palette: [bool; SEGMENT_COUNT],
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TileLightState {
+	None,
+	Up,
+	Right,
+	Down,
+	Left,
+	UpLeftSmall,
+	UpRightSmall,
+	DownLeftSmall,
+	DownRightSmall,
+	UpLeftBig,
+	UpRightBig,
+	DownLeftBig,
+	DownRightBig,
+	Full,
 }
 
-impl SegmentPalette {
-	fn new(segment_hits: &[usize; SEGMENT_COUNT]) -> Self {
-		let palette: [bool; SEGMENT_COUNT] =
-		[
-			// segment_hits[0] > 0, segment_hits[1] > 0, segment_hits[2] > 0,
-			// segment_hits[7] > 0,                      segment_hits[3] > 0,
-			// segment_hits[6] > 0, segment_hits[5] > 0, segment_hits[4] > 0,
-
-			segment_hits[0] > 0,
-			segment_hits[1] > 0,
-			segment_hits[2] > 0,
-			segment_hits[3] > 0,
-			segment_hits[4] > 0,
-			segment_hits[5] > 0,
-			segment_hits[6] > 0,
-			segment_hits[7] > 0,
-		];
+/// Faces and corners ever lit of every tile of a level, indexed by the
+/// same `tile_id` as `LevelConfiguration`. They add up, so a wall lit from
+/// both sides one after another ends up fully revealed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExploredTiles {
+	/// Union of everything lit so far, in `autotile` bits.
+	lit: Vec<u8>,
+	/// Open neighbours as of the last time the tile was lit.
+	neighbors: Vec<u8>,
+	/// Tiles reached by the player's own light, only these end up on
+	/// the minimap.
+	mapped: Vec<bool>,
+}
 
+impl ExploredTiles {
+	pub fn new(tile_count: usize) -> Self {
 		Self {
-			palette,
+			lit: vec![0; tile_count],
+			neighbors: vec![autotile::ALL; tile_count],
+			mapped: vec![false; tile_count],
 		}
 	}
 
-	fn is_up(&self) -> bool {
-		let p = self.palette;
-
-		false
-			|| (p[0] && p[1] && !p[2])
-			|| (p[0] && !p[1] && p[2])
-			|| (!p[0] && p[1] && !p[2])
-			|| (!p[0] && p[1] && p[2])
-			|| (p[0] && p[1] && p[2])
+	pub fn tile_count(&self) -> usize {
+		self.lit.len()
 	}
 
-	fn is_right(&self) -> bool {
-		let p = self.palette;
+	pub fn update(&mut self, tiles: &[TileLightTracing]) {
+		for tile in tiles.iter() {
+			if tile.hits == 0 {
+				continue;
+			}
 
-		false
-			|| (p[2] && p[3] && !p[4])
-			|| (p[2] && !p[3] && p[4])
-			|| (!p[2] && p[3] && !p[4])
-			|| (!p[2] && p[3] && p[4])
-			|| (p[2] && p[3] && p[4])
+			self.lit[tile.tile_id] |= tile.get_lit_mask();
+			self.neighbors[tile.tile_id] = tile.neighbors;
+		}
 	}
 
-	fn is_down(&self) -> bool {
-		let p = self.palette;
+	pub fn map(&mut self, tiles: &[TileLightTracing]) {
+		for tile in tiles.iter() {
+			self.mapped[tile.tile_id] = true;
+		}
+	}
 
-		false
-			|| (p[6] && p[5] && !p[4])
-			|| (p[6] && !p[5] && p[4])
-			|| (!p[6] && p[5] && !p[4])
-			|| (!p[6] && p[5] && p[4])
-			|| (p[6] && p[5] && p[4])
+	pub fn is_explored(&self, tile_id: usize) -> bool {
+		self.lit[tile_id] != 0
 	}
 
-	fn is_left(&self) -> bool {
-		let p = self.palette;
+	pub fn is_mapped(&self, tile_id: usize) -> bool {
+		self.mapped[tile_id]
+	}
 
-		false
-			|| (p[0] && p[7] && !p[6])
-			|| (p[0] && !p[7] && p[6])
-			|| (!p[0] && p[7] && !p[6])
-			|| (!p[0] && p[7] && p[6])
-			|| (p[0] && p[7] && p[6])
+	pub fn shapes(&self, tile_id: usize) -> Vec<TileLightState> {
+		autotile::light_shapes(self.neighbors[tile_id], self.lit[tile_id])
 	}
 
-	fn is_corner0(&self) -> bool {
-		self.palette[0]
+	/// Iterates over `(tile_id, shapes)` of all explored tiles.
+	pub fn iter(&self) -> impl Iterator<Item = (usize, Vec<TileLightState>)> + '_ {
+		(0..self.lit.len())
+			.filter(move |&tile_id| self.is_explored(tile_id))
+			.map(move |tile_id| (tile_id, self.shapes(tile_id)))
 	}
+}
+
+/// How much light reached a segment and of what colour.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SegmentLight {
+	pub intensity: f32,
+	pub color: [f32; 3],
+}
 
-	fn is_corner1(&self) -> bool {
-		self.palette[2]
+impl SegmentLight {
+	pub fn new() -> Self {
+		Self {
+			intensity: 0.0,
+			color: [0.0, 0.0, 0.0],
+		}
 	}
 
-	fn is_corner2(&self) -> bool {
-		self.palette[4]
+	/// Linear falloff from full intensity at the source to nothing at `radius`.
+	pub fn falloff(distance: f32, radius: f32) -> f32 {
+		if radius <= 0.0 {
+			return 0.0;
+		}
+
+		(1.0 - distance / radius).max(0.0).min(1.0)
 	}
 
-	fn is_corner3(&self) -> bool {
-		self.palette[6]
+	/// Light coming from a single source: the brightest ray wins.
+	fn add_hit(&mut self, intensity: f32, color: graphics::Color) {
+		if intensity > self.intensity {
+			self.intensity = intensity;
+			self.color = [color.r, color.g, color.b];
+		}
 	}
-}
 
-#[derive(Debug)]
-enum TileLightState {
-	None,
-	Up,
-	Right,
-	Down,
-	Left,
-	UpLeftSmall,
-	UpRightSmall,
-	DownLeftSmall,
-	DownRightSmall,
-	UpLeftBig,
-	UpRightBig,
-	DownLeftBig,
-	DownRightBig,
-	Full,
+	/// Light coming from different sources adds up, colours are mixed
+	/// proportionally to their intensity.
+	fn merge(&mut self, other: &Self) {
+		let total = self.intensity + other.intensity;
+
+		if total <= 0.0 {
+			return;
+		}
+
+		for i in 0..3 {
+			self.color[i] = (self.color[i] * self.intensity + other.color[i] * other.intensity) / total;
+		}
+
+		self.intensity = total.min(1.0);
+	}
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TileLightTracing {
 	pub tile_id: usize,
 	pub rect: Rect,
 	pub hits: usize,
 	pub segment_hits: [usize; SEGMENT_COUNT],
+	pub segment_light: [SegmentLight; SEGMENT_COUNT],
+	/// Open neighbours of the tile, see `autotile::open_neighbors`.
+	pub neighbors: u8,
 }
 
 impl TileLightTracing {
@@ -135,26 +164,18 @@ impl TileLightTracing {
 			rect,
 			hits: 0,
 			segment_hits: [0; SEGMENT_COUNT],
+			segment_light: [SegmentLight::new(); SEGMENT_COUNT],
+			neighbors: autotile::ALL,
 		}
 	}
 
-	pub fn find_intersection_mut(tiles: &mut Vec<Self>, point: Point2) -> Option<&mut Self> {
-		for tile in tiles.iter_mut() {
-			if tile.rect.contains(point) {
-				return Some(tile);
-			}
-		}
-
-		None
-	}
-
-	pub fn register_hit(&mut self, point: Point2) {
+	pub fn register_hit(&mut self, point: Point2, intensity: f32, color: graphics::Color) {
 		self.hits += 1;
 
 		let w = self.rect.w / 3.0;
 		let h = self.rect.h / 3.0;
 
-		for (segment_id, hit_count) in self.segment_hits.iter_mut().enumerate() {
+		for (segment_id, (hit_count, light)) in self.segment_hits.iter_mut().zip(self.segment_light.iter_mut()).enumerate() {
 			let (x, y) = match segment_id {
 				0 => (self.rect.x + w * 0.0, self.rect.y + h * 0.0),
 				1 => (self.rect.x + w * 1.0, self.rect.y + h * 0.0),
@@ -170,11 +191,27 @@ impl TileLightTracing {
 			let segment = Rect::new(x, y, w, h);
 			if segment.contains(point) {
 				*hit_count += 1;
+				light.add_hit(intensity, color);
 				break;
 			}
 		}
 	}
 
+	/// Adds hits registered on the same tile by another light source.
+	pub fn merge(&mut self, other: &Self) {
+		assert!(self.tile_id == other.tile_id, "Merging different tiles {} and {}!", self.tile_id, other.tile_id);
+
+		self.hits += other.hits;
+
+		for (hit_count, other_count) in self.segment_hits.iter_mut().zip(other.segment_hits.iter()) {
+			*hit_count += *other_count;
+		}
+
+		for (light, other_light) in self.segment_light.iter_mut().zip(other.segment_light.iter()) {
+			light.merge(other_light);
+		}
+	}
+
 	pub fn set_origin(tiles: &mut Vec<Self>, origin: Point2) {
 		for tile in tiles.iter_mut() {
 			tile.rect.x -= origin.x;
@@ -182,226 +219,259 @@ impl TileLightTracing {
 		}
 	}
 
-	fn get_light_state(&self) -> TileLightState {
-		use TileLightState::*;
-
-		if self.hits == 0 {
-			return None;
-		}
-
-		let palette = SegmentPalette::new(&self.segment_hits);
-
-		let up = palette.is_up();
-		let right = palette.is_right();
-		let down = palette.is_down();
-		let left = palette.is_left();
-
+	/// Faces and corners hit by light, in `autotile` bits.
+	/// A lit corner segment lights both faces next to it.
+	pub fn get_lit_mask(&self) -> u8 {
+		let p = self.segment_hits;
+		let mut mask = 0;
 
-		if up && !right && !down && !left {
-			return Up;
+		if p[0] > 0 || p[1] > 0 || p[2] > 0 {
+			mask |= autotile::UP;
 		}
-		if !up && right && !down && !left {
-			return Right;
+		if p[2] > 0 || p[3] > 0 || p[4] > 0 {
+			mask |= autotile::RIGHT;
 		}
-		if !up && !right && down && !left {
-			return Down;
+		if p[4] > 0 || p[5] > 0 || p[6] > 0 {
+			mask |= autotile::DOWN;
 		}
-		if !up && !right && !down && left {
-			return Left;
+		if p[6] > 0 || p[7] > 0 || p[0] > 0 {
+			mask |= autotile::LEFT;
 		}
 
-		if up && right && !down && !left {
-			return UpRightBig;
+		if p[0] > 0 {
+			mask |= autotile::UP_LEFT;
 		}
-		if !up && right && down && !left {
-			return DownRightBig;
+		if p[2] > 0 {
+			mask |= autotile::UP_RIGHT;
 		}
-		if !up && !right && down && left {
-			return DownLeftBig;
+		if p[4] > 0 {
+			mask |= autotile::DOWN_RIGHT;
 		}
-		if up && !right && !down && left {
-			return UpLeftBig;
+		if p[6] > 0 {
+			mask |= autotile::DOWN_LEFT;
 		}
 
-		if up && !right && down && !left {
-			return Full;
-		}
-		if !up && right && !down && left {
-			return Full;
+		mask
+	}
+
+	pub fn get_light_shapes(&self) -> Vec<TileLightState> {
+		if self.hits == 0 {
+			return Vec::new();
 		}
 
-		let c0 = palette.is_corner0();
-		let c1 = palette.is_corner1();
-		let c2 = palette.is_corner2();
-		let c3 = palette.is_corner3();
+		autotile::light_shapes(self.neighbors, self.get_lit_mask())
+	}
 
-		if c0 && !c1 && !c2 && !c3 {
-			return UpLeftSmall;
-		}
-		if !c0 && c1 && !c2 && !c3 {
-			return UpRightSmall;
-		}
-		if !c0 && !c1 && c2 && !c3 {
-			return DownRightSmall;
-		}
-		if !c0 && !c1 && !c2 && c3 {
-			return DownLeftSmall;
-		}
+	/// Average light over all lit segments, as a tint for the tile sprite.
+	pub fn get_light_color(&self) -> graphics::Color {
+		let mut intensity = 0.0;
+		let mut color = [0.0; 3];
+		let mut lit = 0;
 
-		if c0 && !c1 && c2 && !c3 {
-			return Full;
+		for light in self.segment_light.iter() {
+			if light.intensity <= 0.0 {
+				continue;
+			}
+
+			intensity += light.intensity;
+			for i in 0..3 {
+				color[i] += light.color[i];
+			}
+			lit += 1;
 		}
-		if !c0 && c1 && !c2 && c3 {
-			return Full;
+
+		if lit == 0 {
+			return graphics::Color::new(0.0, 0.0, 0.0, 0.0);
 		}
 
-		None
+		let lit = lit as f32;
+
+		graphics::Color::new(color[0] / lit, color[1] / lit, color[2] / lit, intensity / lit)
 	}
 
 	pub fn draw(&self, context: &mut ggez::Context, tiles: &resources::TilePack, level_configuration: &LevelConfiguration) -> ggez::GameResult<()> {
-		let state = self.get_light_state();
-
-		let side_n = level_configuration.get_side(self.tile_id);
-		let corner_n = level_configuration.get_corner(self.tile_id);
-
-		match state {
-			TileLightState::None => {},
-			TileLightState::Up => {
-				graphics::draw(
-					context,
-					&tiles.tile_up[side_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::Right => {
-				graphics::draw(
-					context,
-					&tiles.tile_up[side_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.rotation(90.0 * PI / 180.0)
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::Down => {
-				graphics::draw(
-					context,
-					&tiles.tile_down[side_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::Left => {
-				graphics::draw(
-					context,
-					&tiles.tile_down[side_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.rotation(90.0 * PI / 180.0)
-						.offset(Point2::new(0.5, 0.5))
-						// .color(graphics::Color::new(0.0, 0.0, 0.0, 1.0))
-				)?;
-			},
-			TileLightState::UpLeftSmall => {
-				graphics::draw(
-					context,
-					&tiles.corner_s[corner_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.rotation(90.0 * PI / 180.0)
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::UpRightSmall => {
-				graphics::draw(
-					context,
-					&tiles.corner_s[corner_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.rotation(180.0 * PI / 180.0)
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::DownLeftSmall => {
-				graphics::draw(
-					context,
-					&tiles.corner_s[corner_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::DownRightSmall => {
-				graphics::draw(
-					context,
-					&tiles.corner_s[corner_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.rotation(270.0 * PI / 180.0)
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::UpLeftBig => {
-				graphics::draw(
-					context,
-					&tiles.corner_b[corner_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.rotation(270.0 * PI / 180.0)
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::UpRightBig => {
-				graphics::draw(
-					context,
-					&tiles.corner_b[corner_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::DownLeftBig => {
-				graphics::draw(
-					context,
-					&tiles.corner_b[corner_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.rotation(180.0 * PI / 180.0)
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
-			TileLightState::DownRightBig => {
-				graphics::draw(
-					context,
-					&tiles.corner_b[corner_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.rotation(90.0 * PI / 180.0)
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-			},
+		let color = self.get_light_color();
+
+		for state in self.get_light_shapes() {
+			Self::draw_state(context, tiles, level_configuration, self.tile_id, self.rect, state, color)?;
+		}
+
+		Ok(())
+	}
+
+	pub fn draw_state(
+		context: &mut ggez::Context,
+		tiles: &resources::TilePack,
+		level_configuration: &LevelConfiguration,
+		tile_id: usize,
+		rect: Rect,
+		state: TileLightState,
+		color: graphics::Color,
+	) -> ggez::GameResult<()> {
+		let side_n = level_configuration.get_side(tile_id);
+		let corner_n = level_configuration.get_corner(tile_id);
+
+		let (image, rotation) = match state {
+			TileLightState::None => return Ok(()),
+			TileLightState::Up => (&tiles.tile_up[side_n], 0.0),
+			TileLightState::Right => (&tiles.tile_up[side_n], 90.0),
+			TileLightState::Down => (&tiles.tile_down[side_n], 0.0),
+			TileLightState::Left => (&tiles.tile_down[side_n], 90.0),
+			TileLightState::UpLeftSmall => (&tiles.corner_s[corner_n], 90.0),
+			TileLightState::UpRightSmall => (&tiles.corner_s[corner_n], 180.0),
+			TileLightState::DownLeftSmall => (&tiles.corner_s[corner_n], 0.0),
+			TileLightState::DownRightSmall => (&tiles.corner_s[corner_n], 270.0),
+			TileLightState::UpLeftBig => (&tiles.corner_b[corner_n], 270.0),
+			TileLightState::UpRightBig => (&tiles.corner_b[corner_n], 0.0),
+			TileLightState::DownLeftBig => (&tiles.corner_b[corner_n], 180.0),
+			TileLightState::DownRightBig => (&tiles.corner_b[corner_n], 90.0),
 			TileLightState::Full => {
-				graphics::draw(
-					context,
-					&tiles.tile_up[side_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.offset(Point2::new(0.5, 0.5))
-				)?;
-
-				graphics::draw(
-					context,
-					&tiles.tile_down[side_n].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(self.rect.x + self.rect.w / 2.0, self.rect.y + self.rect.h / 2.0))
-						.offset(Point2::new(0.5, 0.5))
-				)?;
+				draw_image(context, &tiles.corner_b[corner_n], rect, 270.0, color)?;
+
+				(&tiles.corner_b[corner_n], 90.0)
 			},
 		};
 
-		Ok(())
+		draw_image(context, image, rect, rotation, color)
+	}
+}
+
+fn draw_image(context: &mut ggez::Context, image: &warmy::Res<resources::Image>, rect: Rect, rotation: f32, color: graphics::Color) -> ggez::GameResult<()> {
+	graphics::draw(
+		context,
+		&image.borrow().0,
+		graphics::DrawParam::default()
+			.dest(Point2::new(rect.x + rect.w / 2.0, rect.y + rect.h / 2.0))
+			.rotation(rotation * PI / 180.0)
+			.offset(Point2::new(0.5, 0.5))
+			.color(color)
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RED: graphics::Color = graphics::Color { r: 1.0, g: 0.0, b: 0.0, a: 1.0 };
+	const GREEN: graphics::Color = graphics::Color { r: 0.0, g: 1.0, b: 0.0, a: 1.0 };
+	const BLUE: graphics::Color = graphics::Color { r: 0.0, g: 0.0, b: 1.0, a: 1.0 };
+
+	fn assert_close(a: f32, b: f32) {
+		assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+	}
+
+	fn segment(intensity: f32, color: graphics::Color) -> SegmentLight {
+		let mut light = SegmentLight::new();
+		light.add_hit(intensity, color);
+
+		light
+	}
+
+	#[test]
+	fn falloff_is_linear_in_distance() {
+		assert_close(SegmentLight::falloff(0.0, 64.0), 1.0);
+		assert_close(SegmentLight::falloff(16.0, 64.0), 0.75);
+		assert_close(SegmentLight::falloff(32.0, 64.0), 0.5);
+		assert_close(SegmentLight::falloff(48.0, 64.0), 0.25);
+	}
+
+	#[test]
+	fn falloff_is_zero_at_and_past_radius() {
+		assert_close(SegmentLight::falloff(64.0, 64.0), 0.0);
+		assert_close(SegmentLight::falloff(100.0, 64.0), 0.0);
+		assert_close(SegmentLight::falloff(0.0, 0.0), 0.0);
+	}
+
+	#[test]
+	fn add_hit_keeps_brightest() {
+		let mut light = SegmentLight::new();
+		light.add_hit(0.3, RED);
+		light.add_hit(0.8, GREEN);
+		light.add_hit(0.5, BLUE);
+
+		assert_eq!(light, segment(0.8, GREEN));
+	}
+
+	#[test]
+	fn merge_sums_and_mixes_by_intensity() {
+		let mut light = segment(0.2, RED);
+		light.merge(&segment(0.6, BLUE));
+
+		assert_close(light.intensity, 0.8);
+		assert_close(light.color[0], 0.25);
+		assert_close(light.color[1], 0.0);
+		assert_close(light.color[2], 0.75);
+	}
+
+	#[test]
+	fn merge_saturates() {
+		let mut light = segment(0.7, RED);
+		light.merge(&segment(0.7, RED));
+
+		assert_close(light.intensity, 1.0);
+		assert_close(light.color[0], 1.0);
+	}
+
+	#[test]
+	fn merge_with_dark_keeps_light() {
+		let mut light = segment(0.4, GREEN);
+		light.merge(&SegmentLight::new());
+
+		assert_eq!(light, segment(0.4, GREEN));
+	}
+
+	#[test]
+	fn light_color_alpha_is_average_intensity() {
+		let mut tile = TileLightTracing::new(0, Point2::new(16.0, 16.0), 32.0, 32.0);
+		assert_close(tile.get_light_color().a, 0.0);
+
+		// top left and bottom right segments
+		tile.register_hit(Point2::new(1.0, 1.0), 0.4, RED);
+		tile.register_hit(Point2::new(31.0, 31.0), 0.8, BLUE);
+
+		let color = tile.get_light_color();
+
+		assert_close(color.a, 0.6);
+		assert_close(color.r, 0.5);
+		assert_close(color.b, 0.5);
+	}
+
+	#[test]
+	fn merged_tiles_add_up() {
+		let mut tile = TileLightTracing::new(3, Point2::new(16.0, 16.0), 32.0, 32.0);
+		tile.register_hit(Point2::new(1.0, 1.0), 0.5, RED);
+
+		let mut other = TileLightTracing::new(3, Point2::new(16.0, 16.0), 32.0, 32.0);
+		other.register_hit(Point2::new(2.0, 2.0), 0.25, GREEN);
+		other.register_hit(Point2::new(31.0, 31.0), 0.25, GREEN);
+
+		tile.merge(&other);
+
+		assert_eq!(tile.hits, 3);
+		assert_eq!(tile.segment_hits[0], 2);
+		assert_eq!(tile.segment_hits[4], 1);
+		assert_close(tile.segment_light[0].intensity, 0.75);
+	}
+
+	#[test]
+	fn explored_faces_add_up() {
+		// a wall between two corridors, lit from above and then from below
+		let open = autotile::UP | autotile::DOWN | autotile::CORNERS;
+		let mut explored = ExploredTiles::new(1);
+
+		let mut tile = TileLightTracing::new(0, Point2::new(16.0, 16.0), 32.0, 32.0);
+		tile.neighbors = open;
+		tile.register_hit(Point2::new(16.0, 1.0), 1.0, RED);
+		explored.update(&[tile]);
+
+		assert_eq!(explored.shapes(0), vec![TileLightState::Up]);
+
+		let mut tile = TileLightTracing::new(0, Point2::new(16.0, 16.0), 32.0, 32.0);
+		tile.neighbors = open;
+		tile.register_hit(Point2::new(16.0, 31.0), 1.0, RED);
+		explored.update(&[tile]);
+
+		assert_eq!(explored.shapes(0), vec![TileLightState::Up, TileLightState::Down]);
+		assert_eq!(explored.lit[0], autotile::UP | autotile::DOWN);
 	}
 }
@@ -1,22 +1,43 @@
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
+
 #[macro_use]
 extern crate log;
+#[cfg(all(test, feature = "bench"))]
+extern crate test;
 
+use std::collections::VecDeque;
 use std::env;
 use std::path;
 use ggez::{self, *};
 
+mod ascii_map;
+mod autotile;
+mod collision;
 mod components;
+mod generator;
+mod ghost;
 mod input;
 mod level_configuration;
 mod lighting;
+mod movement;
+mod pathfinding;
+mod replay;
 mod resources;
+mod save;
 mod scenes;
+mod simulation;
+mod snapshot;
 mod systems;
+mod tiled_map;
 mod types;
 mod util;
+mod validation;
+mod visibility;
 mod world;
 
 const DESIRED_FPS: u32 = 60;
+/// Updates to catch up on in one frame before giving up and slowing down.
+const MAX_UPDATES_PER_FRAME: usize = 5;
 const DIMENSIONS: (f32, f32) = (1920.0, 1080.0);
 const GAME_ID: &str = "LD45";
 const GAME_TITLE: &str = "LD45";
@@ -25,43 +46,129 @@ const AUTHOR: &str = "PickleMagicle";
 struct MainState {
 	scenes: scenes::Stack,
 	input_binding: input::Binding,
+
+	/// Scene updates so far.
+	frame: u64,
+	/// Written to the path on exit.
+	recording: Option<(path::PathBuf, replay::Replay)>,
+	/// Events still to be fed back, the keyboard is ignored until then.
+	replay: Option<VecDeque<replay::RecordedEvent>>,
 }
 
 impl MainState {
-	fn new(context: &mut Context, resource_path: &path::Path) -> Self {
-		use crate::resources::TransitionList;
-
-		let transition_list = TransitionList::load(context, "/transitions.toml")
-			.expect("Couldn't load transition list!");
+	fn new(
+		context: &mut Context,
+		resource_path: &path::Path,
+		seed: Option<u64>,
+		editor_args: Option<Vec<String>>,
+		record_path: Option<path::PathBuf>,
+		replay: Option<replay::Replay>,
+	) -> Self {
+		let seed = replay.as_ref().map_or(seed, |replay| replay.seed);
+		let mut world = world::World::new(context, resource_path, DIMENSIONS, 1.0 / DESIRED_FPS as f32, seed);
+
+		let recording = record_path.map(|path| {
+			(path, replay::Replay::new(seed, world.save.clone(), world.tick))
+		});
+
+		let replay = replay.map(|replay| {
+			world.tick = replay.dt;
+			world.save = replay.save;
+			world.is_replay = true;
+
+			replay.events.into_iter().collect()
+		});
 
-		let world = world::World::new(context, resource_path, transition_list, DIMENSIONS);
 		let mut scenes = scenes::Stack::new(context, world);
 
-		let initial_scene = scenes.world.initial_scene(context);
-		scenes.push(Box::new(initial_scene));
+		if let Some(editor_args) = editor_args {
+			let editor = scenes::editor::EditorScene::from_args(&mut scenes.world, context, &editor_args)
+				.expect("Couldn't open level editor!");
+			scenes.push(Box::new(editor));
+		}
+		else {
+			let initial_scene = scenes.world.initial_scene(context);
+			scenes.push(Box::new(initial_scene));
+		}
 
 		Self {
 			scenes,
 			input_binding: input::create_input_binding(),
+
+			frame: 0,
+			recording,
+			replay,
 		}
 	}
+
+	/// Dispatches the recorded events that happened before this frame.
+	fn feed_replay(&mut self) {
+		let frame = self.frame;
+		let events = match &mut self.replay {
+			Some(events) => events,
+			None => return,
+		};
+
+		while events.front().map_or(false, |event| event.frame <= frame) {
+			let event = events.pop_front().unwrap();
+			let ev = event.input.to_event();
+
+			self.scenes.world.input.update_effect(ev, event.started);
+			self.scenes.input(ev, event.started);
+		}
+
+		if events.is_empty() {
+			info!("Replay finished at frame {}.", frame);
+			self.replay = None;
+		}
+	}
+
+	fn write_recording(&self) {
+		if let Some((path, recording)) = &self.recording {
+			match recording.write(path) {
+				Ok(()) => info!("Recorded {} events into {:?}.", recording.events.len(), path),
+				Err(e) => error!("Couldn't write recording {:?}: {}", path, e),
+			}
+		}
+	}
+
+	fn handle_input(&mut self, ev: input::Event, started: bool) {
+		if self.replay.is_some() {
+			return;
+		}
+
+		if let Some((_, recording)) = &mut self.recording {
+			recording.record(self.frame, &ev, started);
+		}
+
+		self.scenes.world.input.update_effect(ev, started);
+		self.scenes.input(ev, started);
+	}
 }
 
 impl event::EventHandler for MainState {
 	fn update(&mut self, context: &mut Context) -> GameResult<()> {
-		let mut has_updated = false;
+		let mut updates = 0;
+		let mut skipped = 0;
 
 		while timer::check_update_time(context, DESIRED_FPS) {
-			if has_updated {
+			if updates == MAX_UPDATES_PER_FRAME {
+				skipped += 1;
 				continue;
 			}
 
+			self.feed_replay();
 			self.scenes.update(context);
-			has_updated = true;
+			self.scenes.world.input.update(self.scenes.world.tick);
+			self.frame += 1;
+			updates += 1;
+		}
+
+		if skipped > 0 {
+			debug!("Too far behind, skipped {} updates.", skipped);
 		}
 
 		self.scenes.world.resources.sync(context);
-		self.scenes.world.input.update(timer::duration_to_f64(timer::delta(context)) as f32);
 
 		use ggez::audio::SoundSource;
 		let _ = self.scenes.world.sound_background.play_later();
@@ -88,8 +195,7 @@ impl event::EventHandler for MainState {
 		_repeat: bool,
 	) {
 		if let Some(ev) = self.input_binding.resolve(keycode) {
-			self.scenes.world.input.update_effect(ev, true);
-			self.scenes.input(ev, true);
+			self.handle_input(ev, true);
 			// TODO: update_button_down?
 		}
 	}
@@ -101,8 +207,7 @@ impl event::EventHandler for MainState {
 		_keymod: event::KeyMods,
 	) {
 		if let Some(ev) = self.input_binding.resolve(keycode) {
-			self.scenes.world.input.update_effect(ev, false);
-			self.scenes.input(ev, false);
+			self.handle_input(ev, false);
 			// TODO: update_button_up?
 		}
 	}
@@ -146,6 +251,56 @@ fn main() {
 	};
 	info!("Resource path: {:?}", resource_path);
 
+	let args: Vec<String> = env::args().collect();
+
+	if args.iter().any(|arg| arg == "--validate-levels") {
+		let is_valid = validation::validate_levels(&resource_path);
+
+		std::process::exit(if is_valid { 0 } else { 1 });
+	}
+
+	if let Some(position) = args.iter().position(|arg| arg == "--generate-level") {
+		let is_generated = generator::run_command(&resource_path, &args[position + 1..]);
+
+		std::process::exit(if is_generated { 0 } else { 1 });
+	}
+
+	if let Some(position) = args.iter().position(|arg| arg == "--export-map") {
+		let is_exported = ascii_map::run_command(&args[position + 1..]);
+
+		std::process::exit(if is_exported { 0 } else { 1 });
+	}
+
+	let seed = args.iter()
+		.position(|arg| arg == "--seed")
+		.map(|position| {
+			args.get(position + 1)
+				.and_then(|seed| generator::parse_seed(seed))
+				.unwrap_or_else(|| panic!("--seed expects a number up to {}!", generator::MAX_SEED))
+		});
+
+	let editor_args = args.iter()
+		.position(|arg| arg == "--editor")
+		.map(|position| args[position + 1..].to_vec());
+
+	let record_path = args.iter()
+		.position(|arg| arg == "--record")
+		.map(|position| {
+			args.get(position + 1)
+				.map(path::PathBuf::from)
+				.expect("--record expects a file!")
+		});
+
+	let replay = args.iter()
+		.position(|arg| arg == "--replay")
+		.map(|position| {
+			let path = args.get(position + 1)
+				.expect("--replay expects a file!");
+
+			replay::Replay::load(path::Path::new(path))
+				.expect("Couldn't load replay!")
+		});
+
 	let cb = ContextBuilder::new(GAME_ID, AUTHOR)
 		.window_setup(conf::WindowSetup::default().title(GAME_TITLE))
 		.window_mode(conf::WindowMode::default()
@@ -164,11 +319,13 @@ fn main() {
 	info!("main: screen_coordinates: {:?}", graphics::screen_coordinates(context));
 	// TODO: Fix scale issue, try https://docs.rs/ggez/0.5.1/ggez/graphics/fn.set_screen_coordinates.html
 
-	let state = &mut MainState::new(context, &resource_path);
+	let state = &mut MainState::new(context, &resource_path, seed, editor_args, record_path, replay);
 	if let Err(e) = event::run(context, ev, state) {
 		error!("Error encountered: {}", e);
 	}
 	else {
 		info!("Game exited cleanly.");
 	}
+
+	state.write_recording();
 }
@@ -0,0 +1,207 @@
+//! How the player speeds up, slows down and turns.
+//!
+//! The model comes from `/movement.toml`, every level can override parts
+//! of it (`[movement]`) and mark areas with surfaces that scale it:
+//!
+//! ```toml
+//! [player]
+//! max_speed = 300.0
+//! acceleration = 4000.0
+//! friction = 6000.0
+//! turn_responsiveness = 40.0
+//!
+//! [surfaces.ice]
+//! acceleration = 0.1
+//! friction = 0.05
+//! turn_responsiveness = 0.05
+//! ```
+use ggez;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MovementModel {
+	/// Pixels per second.
+	pub max_speed: f32,
+	/// Pixels per second squared while a direction is held.
+	pub acceleration: f32,
+	/// Pixels per second squared once nothing is held.
+	pub friction: f32,
+	/// How fast sideways speed is lost when changing direction, per
+	/// second. Low values drift.
+	pub turn_responsiveness: f32,
+}
+
+impl Default for MovementModel {
+	fn default() -> Self {
+		Self {
+			max_speed: 300.0,
+			acceleration: 4000.0,
+			friction: 6000.0,
+			turn_responsiveness: 40.0,
+		}
+	}
+}
+
+impl MovementModel {
+	pub fn with_override(&self, changes: &MovementOverride) -> Self {
+		Self {
+			max_speed: changes.max_speed.unwrap_or(self.max_speed),
+			acceleration: changes.acceleration.unwrap_or(self.acceleration),
+			friction: changes.friction.unwrap_or(self.friction),
+			turn_responsiveness: changes.turn_responsiveness.unwrap_or(self.turn_responsiveness),
+		}
+	}
+
+	pub fn on(&self, surface: &Surface) -> Self {
+		Self {
+			max_speed: self.max_speed * surface.max_speed,
+			acceleration: self.acceleration * surface.acceleration,
+			friction: self.friction * surface.friction,
+			turn_responsiveness: self.turn_responsiveness * surface.turn_responsiveness,
+		}
+	}
+}
+
+/// Parts of the model a level changes.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct MovementOverride {
+	pub max_speed: Option<f32>,
+	pub acceleration: Option<f32>,
+	pub friction: Option<f32>,
+	pub turn_responsiveness: Option<f32>,
+}
+
+/// Factors applied to the model while standing on a surface.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Surface {
+	#[serde(default = "Surface::one")]
+	pub max_speed: f32,
+	#[serde(default = "Surface::one")]
+	pub acceleration: f32,
+	#[serde(default = "Surface::one")]
+	pub friction: f32,
+	#[serde(default = "Surface::one")]
+	pub turn_responsiveness: f32,
+}
+
+impl Surface {
+	fn one() -> f32 {
+		1.0
+	}
+}
+
+impl Default for Surface {
+	fn default() -> Self {
+		Self {
+			max_speed: 1.0,
+			acceleration: 1.0,
+			friction: 1.0,
+			turn_responsiveness: 1.0,
+		}
+	}
+}
+
+/// Rectangle of tiles in a level with one of the surfaces.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SurfaceArea {
+	pub surface: String,
+	pub x: usize,
+	pub y: usize,
+	#[serde(default = "SurfaceArea::one")]
+	pub width: usize,
+	#[serde(default = "SurfaceArea::one")]
+	pub height: usize,
+}
+
+impl SurfaceArea {
+	fn one() -> usize {
+		1
+	}
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MovementConfig {
+	#[serde(default)]
+	pub player: MovementModel,
+	#[serde(default)]
+	pub surfaces: BTreeMap<String, Surface>,
+}
+
+impl MovementConfig {
+	/// Without the file the defaults are used.
+	pub fn load<P: AsRef<path::Path>>(context: &mut ggez::Context, file: P) -> ggez::GameResult<Self> {
+		use std::io::Read;
+
+		if !ggez::filesystem::exists(context, &file) {
+			info!("No {:?}, using the default movement.", file.as_ref());
+			return Ok(Self::default());
+		}
+
+		let mut content = String::new();
+		let mut reader = ggez::filesystem::open(context, file)?;
+		let _ = reader.read_to_string(&mut content)?;
+
+		toml::from_str(&content)
+			.map_err(|e| ggez::error::GameError::ResourceLoadError(e.to_string()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn override_replaces_only_given_values() {
+		let changes = MovementOverride {
+			max_speed: Some(100.0),
+			friction: Some(0.0),
+			..MovementOverride::default()
+		};
+
+		let model = MovementModel::default().with_override(&changes);
+
+		assert_eq!(model, MovementModel {
+			max_speed: 100.0,
+			acceleration: 4000.0,
+			friction: 0.0,
+			turn_responsiveness: 40.0,
+		});
+	}
+
+	#[test]
+	fn surface_scales_the_overridden_model() {
+		let changes = MovementOverride {
+			max_speed: Some(100.0),
+			..MovementOverride::default()
+		};
+		let surface = Surface {
+			max_speed: 0.5,
+			friction: 0.1,
+			..Surface::default()
+		};
+
+		let model = MovementModel::default().with_override(&changes).on(&surface);
+
+		assert_eq!(model, MovementModel {
+			max_speed: 50.0,
+			acceleration: 4000.0,
+			friction: 600.0,
+			turn_responsiveness: 40.0,
+		});
+	}
+
+	#[test]
+	fn missing_values_default() {
+		let config: MovementConfig = toml::from_str("[player]\nmax_speed = 200.0\nacceleration = 1000.0\nfriction = 1000.0\nturn_responsiveness = 10.0\n\n[surfaces.ice]\nfriction = 0.05\n").unwrap();
+
+		assert_eq!(config.player.max_speed, 200.0);
+		assert_eq!(config.surfaces["ice"], Surface { friction: 0.05, ..Surface::default() });
+
+		let config: MovementConfig = toml::from_str("").unwrap();
+
+		assert_eq!(config.player, MovementModel::default());
+		assert!(config.surfaces.is_empty());
+	}
+}
@@ -0,0 +1,230 @@
+//! Checks whether a level can actually be finished.
+//!
+//! The player's hitbox is flood filled over a fine grid of positions
+//! starting at `player_x`/`player_y`, using the same collision test as
+//! the simulation, so corridors the player can't squeeze through count
+//! as walls here too.
+use crate::resources::{Level, PickUpEffect};
+use crate::simulation::{self, PICK_UP_DISTANCE, PLAYER_HEIGHT, PLAYER_WIDTH, WALL_SIZE};
+use crate::types::{Point2, Rect};
+use crate::util;
+use std::collections::VecDeque;
+
+/// Distance between two neighbouring positions of the flood fill.
+/// Must stay well below `PLAYER_WIDTH` so no wall can be skipped.
+const GRID_STEP: f32 = 4.0;
+
+#[derive(Clone, Debug)]
+pub struct Reachability {
+	/// Parallel to `Level::entities`.
+	pub reachable_entities: Vec<bool>,
+	/// Number of reachable `ActivateDoors` shards.
+	pub reachable_shards: usize,
+	pub required_shards: usize,
+	pub is_door_reachable: bool,
+}
+
+impl Reachability {
+	pub fn has_enough_shards(&self) -> bool {
+		self.reachable_shards >= self.required_shards
+	}
+
+	pub fn is_solvable(&self) -> bool {
+		self.has_enough_shards() && self.is_door_reachable
+	}
+}
+
+pub fn check_reachability(level: &Level) -> Reachability {
+	let start = Point2::new(level.player_x * WALL_SIZE, level.player_y * WALL_SIZE);
+
+	// grid of positions reachable with whole steps from the start
+	let min_x = -(start.x / GRID_STEP).floor() as isize;
+	let min_y = -(start.y / GRID_STEP).floor() as isize;
+	let max_x = ((level.width as f32 * WALL_SIZE - start.x) / GRID_STEP).floor() as isize;
+	let max_y = ((level.height as f32 * WALL_SIZE - start.y) / GRID_STEP).floor() as isize;
+
+	let columns = (max_x - min_x + 1).max(0) as usize;
+	let rows = (max_y - min_y + 1).max(0) as usize;
+
+	let position = |x: isize, y: isize| {
+		Point2::new(start.x + x as f32 * GRID_STEP, start.y + y as f32 * GRID_STEP)
+	};
+	let index = |x: isize, y: isize| {
+		(y - min_y) as usize * columns + (x - min_x) as usize
+	};
+
+	let mut reachable_entities = vec![false; level.entities.len()];
+	let mut is_door_reachable = false;
+
+	let mut visited = vec![false; columns * rows];
+	let mut queue = VecDeque::new();
+
+	if columns > 0 && rows > 0 && !simulation::is_blocked(level, player_rect(start), false) {
+		visited[index(0, 0)] = true;
+		queue.push_back((0, 0));
+	}
+
+	while let Some((x, y)) = queue.pop_front() {
+		let current = position(x, y);
+
+		for (entity_n, entity) in level.entities.iter().enumerate() {
+			let entity_position = Point2::new(entity.x * WALL_SIZE, entity.y * WALL_SIZE);
+
+			if util::get_distance(entity_position, current) < PICK_UP_DISTANCE {
+				reachable_entities[entity_n] = true;
+			}
+		}
+
+		let neighbors = [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)];
+
+		for &(nx, ny) in neighbors.iter() {
+			if nx < min_x || ny < min_y || nx > max_x || ny > max_y {
+				continue;
+			}
+
+			let rect = player_rect(position(nx, ny));
+
+			if simulation::is_blocked(level, rect, false) {
+				// blocked only while doors are closed, so it's a door
+				if !simulation::is_blocked(level, rect, true) {
+					is_door_reachable = true;
+				}
+
+				continue;
+			}
+
+			if visited[index(nx, ny)] {
+				continue;
+			}
+
+			visited[index(nx, ny)] = true;
+			queue.push_back((nx, ny));
+		}
+	}
+
+	let reachable_shards = level.entities.iter()
+		.zip(reachable_entities.iter())
+		.filter(|(entity, &reachable)| reachable && match entity.effect {
+			PickUpEffect::ActivateDoors => true,
+			_ => false,
+		})
+		.count();
+
+	Reachability {
+		reachable_entities,
+		reachable_shards,
+		required_shards: level.shards_for_door_activation,
+		is_door_reachable,
+	}
+}
+
+fn player_rect(center: Point2) -> Rect {
+	Rect::new(
+		center.x - PLAYER_WIDTH / 2.0,
+		center.y - PLAYER_HEIGHT / 2.0,
+		PLAYER_WIDTH,
+		PLAYER_HEIGHT,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::resources::{Entity, EntityType, Wall};
+
+	/// A `width` by 3 corridor walled all around, with the player at its
+	/// left end.
+	fn corridor(width: usize) -> Level {
+		let mut level = Level::new(width, 3, "test");
+
+		for x in 0..width {
+			level.set(x, 0, Wall::S);
+			level.set(x, 2, Wall::S);
+		}
+
+		level.set(0, 1, Wall::S);
+		level.set(width - 1, 1, Wall::S);
+
+		level.player_x = 1.5;
+		level.player_y = 1.5;
+
+		level
+	}
+
+	fn shard(x: f32, y: f32) -> Entity {
+		Entity {
+			entity_type: EntityType::Shard0,
+			x,
+			y,
+			light_radius: 0.0,
+			effect: PickUpEffect::ActivateDoors,
+		}
+	}
+
+	#[test]
+	fn walled_off_shard() {
+		let mut level = corridor(8);
+		level.set(4, 1, Wall::S);
+		level.entities.push(shard(2.5, 1.5));
+		level.entities.push(shard(5.5, 1.5));
+		level.shards_for_door_activation = 2;
+
+		let reachability = check_reachability(&level);
+
+		assert_eq!(reachability.reachable_entities, vec![true, false]);
+		assert_eq!(reachability.reachable_shards, 1);
+		assert!(!reachability.has_enough_shards());
+	}
+
+	#[test]
+	fn doors_stop_the_flood_fill() {
+		// player | door | shard | door, the second door is only behind the first
+		let mut level = Level::new(8, 4, "test");
+
+		for x in 0..8 {
+			level.set(x, 0, Wall::S);
+			level.set(x, 3, Wall::S);
+		}
+
+		level.set(0, 1, Wall::S);
+		level.set(0, 2, Wall::S);
+
+		for &x in [3, 7].iter() {
+			level.set(x, 1, Wall::B0V);
+			level.set(x, 2, Wall::B1V);
+		}
+
+		level.player_x = 1.5;
+		level.player_y = 2.0;
+		level.entities.push(shard(5.0, 2.0));
+		level.shards_for_door_activation = 1;
+
+		let reachability = check_reachability(&level);
+
+		assert!(reachability.is_door_reachable);
+		assert_eq!(reachability.reachable_entities, vec![false]);
+		assert!(!reachability.is_solvable());
+
+		// without the first door the second one can't be reached either
+		level.set(3, 1, Wall::S);
+		level.set(3, 2, Wall::S);
+
+		assert!(!check_reachability(&level).is_door_reachable);
+	}
+
+	#[test]
+	fn open_level() {
+		let mut level = Level::new(5, 5, "test");
+		level.entities.push(shard(0.5, 0.5));
+		level.entities.push(shard(4.5, 4.5));
+		level.entities.push(shard(0.5, 4.5));
+		level.shards_for_door_activation = 3;
+
+		let reachability = check_reachability(&level);
+
+		assert_eq!(reachability.reachable_entities, vec![true, true, true]);
+		assert_eq!(reachability.reachable_shards, 3);
+		assert!(reachability.has_enough_shards());
+		assert!(!reachability.is_door_reachable);
+	}
+}
@@ -0,0 +1,154 @@
+//! Recording input with frame numbers and feeding it back.
+//!
+//! Scenes run on a fixed timestep (see `World::tick`), so the same input
+//! on the same frames plays out exactly the same way.
+use crate::input::{self, Axis, Button};
+use crate::save::SaveGame;
+use ggez_goodies::input::InputEffect;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum RecordedInput {
+	Axis(Axis, bool),
+	Button(Button),
+}
+
+impl RecordedInput {
+	fn from_event(event: &input::Event) -> Self {
+		match event {
+			InputEffect::Axis(axis, positive) => RecordedInput::Axis(*axis, *positive),
+			InputEffect::Button(button) => RecordedInput::Button(*button),
+		}
+	}
+
+	pub fn to_event(&self) -> input::Event {
+		match self {
+			RecordedInput::Axis(axis, positive) => InputEffect::Axis(*axis, *positive),
+			RecordedInput::Button(button) => InputEffect::Button(*button),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedEvent {
+	/// Number of scene updates before the event.
+	pub frame: u64,
+	pub input: RecordedInput,
+	pub started: bool,
+}
+
+/// Stored as JSON, seeds don't fit into TOML integers.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Replay {
+	/// `World::seed` of the recorded run.
+	pub seed: Option<u64>,
+	/// Progress at the start, so "Continue" goes to the same place.
+	pub save: SaveGame,
+	pub dt: f32,
+	pub events: Vec<RecordedEvent>,
+}
+
+impl Replay {
+	pub fn new(seed: Option<u64>, save: SaveGame, dt: f32) -> Self {
+		Self {
+			seed,
+			save,
+			dt,
+			events: Vec::new(),
+		}
+	}
+
+	pub fn record(&mut self, frame: u64, event: &input::Event, started: bool) {
+		self.events.push(RecordedEvent {
+			frame,
+			input: RecordedInput::from_event(event),
+			started,
+		});
+	}
+
+	pub fn load(path: &path::Path) -> Result<Self, String> {
+		let content = fs::read_to_string(path)
+			.map_err(|e| e.to_string())?;
+
+		Self::parse(&content)
+	}
+
+	/// Events are sorted by frame, since they're fed back in that order.
+	/// Ones on the same frame keep their recorded order.
+	pub fn parse(content: &str) -> Result<Self, String> {
+		let mut replay: Self = serde_json::from_str(content)
+			.map_err(|e| e.to_string())?;
+
+		if replay.dt <= 0.0 {
+			return Err(format!("Replay has a timestep of {}!", replay.dt));
+		}
+
+		replay.events.sort_by_key(|event| event.frame);
+
+		Ok(replay)
+	}
+
+	pub fn write(&self, path: &path::Path) -> Result<(), String> {
+		let content = serde_json::to_string_pretty(self)
+			.map_err(|e| e.to_string())?;
+
+		fs::write(path, content)
+			.map_err(|e| e.to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn events(replay: &Replay) -> Vec<(u64, input::Event, bool)> {
+		replay.events.iter()
+			.map(|event| (event.frame, event.input.to_event(), event.started))
+			.collect()
+	}
+
+	#[test]
+	fn replay_round_trip() {
+		let mut save = SaveGame::default();
+		save.transition = Some("level_1".to_owned());
+
+		let mut replay = Replay::new(Some(std::u64::MAX), save, 1.0 / 60.0);
+		replay.record(3, &InputEffect::Axis(Axis::Horz, true), true);
+		replay.record(3, &InputEffect::Button(Button::QuickSave), true);
+		replay.record(10, &InputEffect::Axis(Axis::Horz, true), false);
+
+		let loaded = Replay::parse(&serde_json::to_string(&replay).unwrap()).unwrap();
+
+		assert_eq!(loaded.seed, Some(std::u64::MAX));
+		assert_eq!(loaded.save.transition.as_ref().map(String::as_str), Some("level_1"));
+		assert_eq!(loaded.dt, 1.0 / 60.0);
+		assert_eq!(events(&loaded), events(&replay));
+	}
+
+	#[test]
+	fn events_come_back_in_frame_order() {
+		let mut replay = Replay::new(None, SaveGame::default(), 1.0 / 60.0);
+		replay.record(8, &InputEffect::Button(Button::Next), true);
+		replay.record(2, &InputEffect::Axis(Axis::Vert, false), true);
+		replay.record(8, &InputEffect::Button(Button::Next), false);
+		replay.record(5, &InputEffect::Axis(Axis::Vert, false), false);
+
+		let loaded = Replay::parse(&serde_json::to_string(&replay).unwrap()).unwrap();
+
+		assert_eq!(events(&loaded), vec![
+			(2, InputEffect::Axis(Axis::Vert, false), true),
+			(5, InputEffect::Axis(Axis::Vert, false), false),
+			(8, InputEffect::Button(Button::Next), true),
+			(8, InputEffect::Button(Button::Next), false),
+		]);
+	}
+
+	#[test]
+	fn replay_needs_a_timestep() {
+		let replay = Replay::new(None, SaveGame::default(), 0.0);
+
+		assert!(Replay::parse(&serde_json::to_string(&replay).unwrap()).is_err());
+	}
+}
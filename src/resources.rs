@@ -1,14 +1,22 @@
-use crate::types::{Error, Point2};
+use crate::ascii_map::{self, LegendEntry};
+use crate::generator::GeneratorOptions;
+use crate::movement::{MovementOverride, SurfaceArea};
+use crate::tiled_map;
+use crate::types::{Error, Point2, Rect};
+use crate::validation;
 use crate::world::World;
 use ggez::{self, graphics};
 // use ggez_goodies::scene;
-use log::{debug, info};
-use serde::{Deserialize};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path;
 use warmy;
 
 pub const TILE_COUNT: usize = 8;
 pub const CORNER_COUNT: usize = 2;
+/// Level file formats, in the order they're looked for.
+pub const LEVEL_EXTENSIONS: [&str; 4] = ["toml", "tmx", "json", "tmj"];
 
 fn warmy_to_ggez_path(path: &path::Path, root: &path::Path) -> path::PathBuf {
     let stripped_path = path
@@ -52,7 +60,7 @@ impl warmy::key::Key for ResourceKey {
 /// is what actually stores things, and the `Storage` is I think
 /// a handle to it.
 pub type Store = warmy::Store<ggez::Context, ResourceKey>;
-type Storage = warmy::Storage<ggez::Context, ResourceKey>;
+pub type Storage = warmy::Storage<ggez::Context, ResourceKey>;
 pub type Loaded<T> = warmy::Loaded<T, ResourceKey>;
 
 /// A wrapper for a ggez Image, so we can implement warmy's `Load` trait on it.
@@ -83,7 +91,7 @@ impl warmy::Load<ggez::Context, ResourceKey> for Image {
 //
 //
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum Wall {
 	// None, nothing, empty.
 	N,
@@ -156,6 +164,90 @@ impl Wall {
 			Self::D2V => true,
 		}
 	}
+
+	/// For door tiles returns which part of the door it is, how many
+	/// tiles the door has and whether it's horizontal.
+	pub fn door_part(&self) -> Option<DoorPart> {
+		let (index, length, horizontal) = match self {
+			Self::N => return None,
+			Self::S => return None,
+			Self::B0H => (0, 2, true),
+			Self::B1H => (1, 2, true),
+			Self::B0V => (0, 2, false),
+			Self::B1V => (1, 2, false),
+			Self::D0H => (0, 3, true),
+			Self::D1H => (1, 3, true),
+			Self::D2H => (2, 3, true),
+			Self::D0V => (0, 3, false),
+			Self::D1V => (1, 3, false),
+			Self::D2V => (2, 3, false),
+		};
+
+		Some(DoorPart {
+			index,
+			length,
+			horizontal,
+		})
+	}
+
+	pub fn from_door_part(part: DoorPart) -> Option<Self> {
+		match (part.index, part.length, part.horizontal) {
+			(0, 2, true) => Some(Self::B0H),
+			(1, 2, true) => Some(Self::B1H),
+			(0, 2, false) => Some(Self::B0V),
+			(1, 2, false) => Some(Self::B1V),
+			(0, 3, true) => Some(Self::D0H),
+			(1, 3, true) => Some(Self::D1H),
+			(2, 3, true) => Some(Self::D2H),
+			(0, 3, false) => Some(Self::D0V),
+			(1, 3, false) => Some(Self::D1V),
+			(2, 3, false) => Some(Self::D2V),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DoorPart {
+	pub index: usize,
+	pub length: usize,
+	pub horizontal: bool,
+}
+
+/// A whole door, at its first tile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Door {
+	pub x: usize,
+	pub y: usize,
+	pub length: usize,
+	pub horizontal: bool,
+}
+
+impl Door {
+	pub fn contains(&self, x: usize, y: usize) -> bool {
+		if self.horizontal {
+			y == self.y && x >= self.x && x < self.x + self.length
+		}
+		else {
+			x == self.x && y >= self.y && y < self.y + self.length
+		}
+	}
+
+	pub fn rect(&self, tile_size: f32) -> Rect {
+		let (width, height) = if self.horizontal {
+			(self.length, 1)
+		}
+		else {
+			(1, self.length)
+		};
+
+		Rect::new(
+			self.x as f32 * tile_size,
+			self.y as f32 * tile_size,
+			width as f32 * tile_size,
+			height as f32 * tile_size,
+		)
+	}
 }
 
 pub struct TilePack {
@@ -265,7 +357,7 @@ impl TilePack {
 //
 //
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum EntityType {
 	Shard0,
 	Shard1,
@@ -274,13 +366,13 @@ pub enum EntityType {
 	Shard4,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum PickUpEffect {
 	IncreasePlayerLightRadius,
 	ActivateDoors,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Entity {
 	pub entity_type: EntityType,
 	pub x: f32,
@@ -289,23 +381,198 @@ pub struct Entity {
 	pub effect: PickUpEffect,
 }
 
-#[derive(Debug, Deserialize)]
+/// A static light placed by the level designer.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Light {
+	pub x: f32,
+	pub y: f32,
+	pub radius: f32,
+	#[serde(default = "Light::default_color")]
+	pub color: [f32; 3],
+}
+
+impl Light {
+	fn default_color() -> [f32; 3] {
+		[1.0, 1.0, 1.0]
+	}
+}
+
+/// Sends the player to another transition than the next one when they
+/// leave through the door covering `x`, `y`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Exit {
+	pub x: usize,
+	pub y: usize,
+	pub transition: String,
+}
+
+/// Pins the wall variants of a single tile instead of picking them randomly.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TileVariant {
+	pub x: usize,
+	pub y: usize,
+
+	/// `0..TILE_COUNT`
+	#[serde(default)]
+	pub side: Option<usize>,
+	/// `0..CORNER_COUNT`
+	#[serde(default)]
+	pub corner: Option<usize>,
+}
+
+// NOTE: When serializing into TOML plain values have to go before tables,
+// so keep `entities`, `lights` and such at the end.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Level {
-	pub walls: Vec<Wall>,
+	#[serde(default)]
 	pub width: usize,
+	#[serde(default)]
 	pub height: usize,
 	pub key: String,
 
+	#[serde(default)]
 	pub player_x: f32,
+	#[serde(default)]
 	pub player_y: f32,
 	pub player_light_radius: f32,
 
 	pub shards_for_door_activation: usize,
 
+	/// Keep walls on screen (dimmed) after they leave the light.
+	#[serde(default)]
+	pub remember_walls: bool,
+
+	#[serde(default)]
+	pub hide_minimap: bool,
+
+	/// Pixels of a wall corner the player slips around instead of getting
+	/// stuck on it.
+	#[serde(default = "Level::default_corner_tolerance")]
+	pub corner_tolerance: f32,
+
+	/// Seed for the random wall variants, random on every load if missing.
+	#[serde(default)]
+	pub seed: Option<u64>,
+
+	/// Replaces `walls`, see `ascii_map`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub map: Option<String>,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub walls: Vec<Wall>,
+
+	#[serde(default)]
 	pub entities: Vec<Entity>,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub lights: Vec<Light>,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub exits: Vec<Exit>,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub tile_variants: Vec<TileVariant>,
+
+	/// Changes to `movement.toml` for this level.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub movement: Option<MovementOverride>,
+
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub surfaces: Vec<SurfaceArea>,
+
+	/// Entities for the digits in `map`.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub legend: BTreeMap<String, LegendEntry>,
+
+	/// Whether the level was loaded from a `map`, so tools can save it back that way.
+	#[serde(skip)]
+	pub uses_map: bool,
+
+	/// Hash of the file the level was parsed from, see `update_content_hash`
+	/// for levels made in code.
+	#[serde(skip)]
+	pub content_hash: u64,
 }
 
 impl Level {
+	/// An empty level with the player in the middle.
+	pub fn new(width: usize, height: usize, key: &str) -> Self {
+		Self {
+			width,
+			height,
+			key: key.to_owned(),
+
+			player_x: width as f32 / 2.0,
+			player_y: height as f32 / 2.0,
+			player_light_radius: 0.0,
+
+			shards_for_door_activation: 0,
+
+			remember_walls: false,
+			hide_minimap: false,
+
+			corner_tolerance: Self::default_corner_tolerance(),
+
+			seed: None,
+
+			map: None,
+
+			walls: vec![Wall::N; width * height],
+
+			entities: Vec::new(),
+
+			lights: Vec::new(),
+
+			exits: Vec::new(),
+
+			tile_variants: Vec::new(),
+
+			movement: None,
+
+			surfaces: Vec::new(),
+
+			legend: BTreeMap::new(),
+
+			uses_map: false,
+
+			content_hash: 0,
+		}
+	}
+
+	fn default_corner_tolerance() -> f32 {
+		6.0
+	}
+
+	/// Every door, found by its first tile.
+	pub fn doors(&self) -> Vec<Door> {
+		let mut doors = Vec::new();
+
+		for y in 0..self.height {
+			for x in 0..self.width {
+				match self.get(x, y).door_part() {
+					Some(part) if part.index == 0 => doors.push(Door {
+						x,
+						y,
+						length: part.length,
+						horizontal: part.horizontal,
+					}),
+					_ => {},
+				}
+			}
+		}
+
+		doors
+	}
+
+	pub fn exit_for(&self, door: &Door) -> Option<&Exit> {
+		self.exits.iter()
+			.find(|exit| door.contains(exit.x, exit.y))
+	}
+
+	pub fn set(&mut self, x: usize, y: usize, wall: Wall) {
+		self.walls[self.width * y + x] = wall;
+	}
+
 	pub fn get(&self, x: usize, y: usize) -> Wall {
 		self.walls[self.width * y + x].clone()
 	}
@@ -314,13 +581,68 @@ impl Level {
 		use std::io::Read;
 
 		let mut content = String::new();
-		let mut reader = ggez::filesystem::open(context, file)?;
+		let mut reader = ggez::filesystem::open(context, &file)?;
 		let _ = reader.read_to_string(&mut content)?;
 
-		let level: Self = toml::from_str(&content)
+		let level = Self::parse_file(file.as_ref(), &content)?;
+
+		for diagnostic in validation::validate(&level) {
+			warn!("{:?}: {}", file.as_ref(), diagnostic);
+		}
+
+		Ok(level)
+	}
+
+	/// Path of the level called `name` in whichever format it exists,
+	/// `/levels/{name}.toml` if there's none.
+	pub fn find_path(context: &ggez::Context, name: &str) -> String {
+		for extension in LEVEL_EXTENSIONS.iter() {
+			let path = format!("/levels/{}.{}", name, extension);
+
+			if ggez::filesystem::exists(context, &path) {
+				return path;
+			}
+		}
+
+		format!("/levels/{}.toml", name)
+	}
+
+	/// Parses a level in the format given by the file's extension.
+	pub fn parse_file(file: &path::Path, content: &str) -> ggez::GameResult<Self> {
+		let mut level = match file.extension().and_then(|extension| extension.to_str()) {
+			Some("tmx") => tiled_map::parse_tmx(content),
+			Some("json") | Some("tmj") => tiled_map::parse_json(content),
+			_ => Ok(Self::parse(content)?),
+		}.map_err(|e| ggez::error::GameError::ResourceLoadError(e))?;
+
+		level.content_hash = hash_content(content);
+
+		Ok(level)
+	}
+
+	/// `content_hash` for levels made in code, taken from the level as
+	/// it would be saved.
+	pub fn update_content_hash(&mut self) {
+		match toml::to_string(self) {
+			Ok(content) => self.content_hash = hash_content(&content),
+			Err(e) => warn!("Couldn't hash level: {}", e),
+		}
+	}
+
+	/// Parses a TOML level, only rejecting ones that can't be used at all.
+	/// Use `validation::validate` for everything else.
+	pub fn parse(content: &str) -> ggez::GameResult<Self> {
+		let mut level: Self = toml::from_str(content)
 			.map_err(|e| ggez::error::GameError::ResourceLoadError(e.to_string()))?;
 
-		assert!(level.walls.len() == level.width * level.height);
+		ascii_map::apply(&mut level)
+			.map_err(|e| ggez::error::GameError::ResourceLoadError(e))?;
+
+		if level.walls.len() != level.width * level.height {
+			return Err(ggez::error::GameError::ResourceLoadError(format!(
+				"Level has {} walls, but is {}x{}!", level.walls.len(), level.width, level.height
+			)));
+		}
 
 		Ok(level)
 	}
@@ -363,6 +685,7 @@ impl warmy::Load<ggez::Context, ResourceKey> for Level {
 #[derive(Clone, Debug, Deserialize)]
 pub enum TransitionType {
 	ToLevel,
+	ToGeneratedLevel,
 	ToScreen,
 }
 
@@ -370,14 +693,15 @@ pub enum TransitionType {
 pub struct Transition {
 	pub name: String,
 	pub transition_type: TransitionType,
+
+	/// Only used by `ToGeneratedLevel`.
+	#[serde(default)]
+	pub generator: Option<GeneratorOptions>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct TransitionList {
 	pub transitions: Vec<Transition>,
-
-	#[serde(skip)]
-	pub current_n: usize,
 }
 
 impl TransitionList {
@@ -388,9 +712,58 @@ impl TransitionList {
 		let mut reader = ggez::filesystem::open(context, file)?;
 		let _ = reader.read_to_string(&mut content)?;
 
-		let list: Self = toml::from_str(&content)
+		Self::parse(&content)
+	}
+
+	pub fn parse(content: &str) -> ggez::GameResult<Self> {
+		let list: Self = toml::from_str(content)
 			.map_err(|e| ggez::error::GameError::ResourceLoadError(e.to_string()))?;
 
+		if list.transitions.is_empty() {
+			return Err(ggez::error::GameError::ResourceLoadError("Transition list is empty!".to_owned()));
+		}
+
 		Ok(list)
 	}
 }
+
+impl warmy::Load<ggez::Context, ResourceKey> for TransitionList {
+	type Error = Error;
+
+	fn load(
+		key: ResourceKey,
+		_storage: &mut Storage,
+		context: &mut ggez::Context,
+	) -> Result<Loaded<Self>, Self::Error> {
+		debug!("Loading transition list {:?}", key);
+
+		match key {
+			ResourceKey::Path(path) => {
+				TransitionList::load(context, &path)
+					.map(|x| warmy::Loaded::from(x))
+					.map_err(|e| Error::GgezError(e))
+			},
+		}
+	}
+}
+
+/// 64 bit FNV-1a. The hash ends up in quicksaves and ghosts, so unlike
+/// `DefaultHasher` it must stay the same between Rust releases.
+fn hash_content(content: &str) -> u64 {
+	const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+	const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+	content.bytes().fold(OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn content_hash_is_fnv_1a() {
+		assert_eq!(hash_content(""), 0xcbf2_9ce4_8422_2325);
+		assert_eq!(hash_content("a"), 0xaf63_dc4c_8601_ec8c);
+		assert_eq!(hash_content("foobar"), 0x8594_4171_f739_67e8);
+	}
+}
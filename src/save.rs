@@ -0,0 +1,150 @@
+//! Progress through the transition list, kept in the user data directory
+//! so it survives quitting the game.
+use ggez;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path;
+
+const SAVE_FILE: &str = "save.toml";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct LevelStats {
+	pub completions: usize,
+	/// Fastest completion in seconds.
+	pub best_time: Option<f32>,
+	/// Most shards collected in a single completion.
+	pub most_shards: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Settings {
+	#[serde(default = "Settings::default_show_ghosts")]
+	pub show_ghosts: bool,
+}
+
+impl Settings {
+	fn default_show_ghosts() -> bool {
+		true
+	}
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			show_ghosts: Self::default_show_ghosts(),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SaveGame {
+	/// Transition to continue from, `None` if there's nothing to continue.
+	#[serde(default)]
+	pub transition: Option<String>,
+	/// Index of `transition`, only a hint like in `World::current_transition`.
+	#[serde(default)]
+	pub transition_n: usize,
+	/// Shards collected in all finished levels.
+	#[serde(default)]
+	pub shards_collected: usize,
+	#[serde(default)]
+	pub settings: Settings,
+	/// Keyed by transition name.
+	#[serde(default)]
+	pub levels: BTreeMap<String, LevelStats>,
+}
+
+impl SaveGame {
+	fn path(context: &ggez::Context) -> path::PathBuf {
+		ggez::filesystem::user_data_dir(context).join(SAVE_FILE)
+	}
+
+	/// A missing or broken save starts a new game.
+	pub fn load(context: &ggez::Context) -> Self {
+		let path = Self::path(context);
+
+		if !path.exists() {
+			return Self::default();
+		}
+
+		let save = fs::read_to_string(&path)
+			.map_err(|e| e.to_string())
+			.and_then(|content| toml::from_str(&content).map_err(|e| e.to_string()));
+
+		match save {
+			Ok(save) => save,
+			Err(e) => {
+				warn!("Couldn't read save {:?}, starting a new game: {}", path, e);
+
+				Self::default()
+			},
+		}
+	}
+
+	pub fn write(&self, context: &ggez::Context) -> Result<(), String> {
+		let path = Self::path(context);
+
+		let content = toml::to_string(self)
+			.map_err(|e| e.to_string())?;
+
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)
+				.map_err(|e| e.to_string())?;
+		}
+
+		fs::write(&path, content)
+			.map_err(|e| e.to_string())
+	}
+
+	pub fn record_level(&mut self, name: &str, time: f32, shards: usize) {
+		let stats = self.levels.entry(name.to_owned()).or_default();
+
+		stats.completions += 1;
+		stats.best_time = Some(stats.best_time.map_or(time, |best| best.min(time)));
+		stats.most_shards = stats.most_shards.max(shards);
+
+		self.shards_collected += shards;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn save_round_trip() {
+		let mut save = SaveGame::default();
+		save.transition = Some("level_2".to_owned());
+		save.transition_n = 3;
+		save.settings.show_ghosts = false;
+		save.record_level("level_1", 12.5, 2);
+		save.record_level("level_1", 10.0, 1);
+		save.record_level("level_2", 30.0, 0);
+
+		let content = toml::to_string(&save).unwrap();
+		let loaded: SaveGame = toml::from_str(&content).unwrap();
+
+		assert_eq!(toml::to_string(&loaded).unwrap(), content);
+		assert_eq!(loaded.transition.as_ref().map(String::as_str), Some("level_2"));
+		assert_eq!(loaded.transition_n, 3);
+		assert_eq!(loaded.shards_collected, 3);
+		assert!(!loaded.settings.show_ghosts);
+
+		let stats = &loaded.levels["level_1"];
+		assert_eq!(stats.completions, 2);
+		assert_eq!(stats.best_time, Some(10.0));
+		assert_eq!(stats.most_shards, 2);
+		assert_eq!(loaded.levels["level_2"].best_time, Some(30.0));
+	}
+
+	#[test]
+	fn empty_save_is_a_new_game() {
+		let save: SaveGame = toml::from_str("").unwrap();
+
+		assert_eq!(save.transition, None);
+		assert_eq!(save.shards_collected, 0);
+		assert!(save.settings.show_ghosts);
+		assert!(save.levels.is_empty());
+	}
+}
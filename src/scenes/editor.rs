@@ -0,0 +1,476 @@
+//! Level editor.
+//!
+//! Keyboard only: arrows/WASD move the cursor, Tab switches the brush,
+//! Space paints, `[` and `]` change the light radius of shards, F2 saves
+//! and Enter playtests the level as it is right now.
+use crate::ascii_map;
+use crate::input;
+use crate::resources::{DoorPart, Entity, EntityType, Level, PickUpEffect, Wall};
+use crate::scenes::labyrinth::LabyrinthScene;
+use crate::scenes;
+use crate::simulation::{PLAYER_WIDTH, WALL_SIZE};
+use crate::types::Point2;
+use crate::validation;
+use crate::world::World;
+use ggez::graphics;
+use ggez;
+use ggez_goodies::input::InputEffect;
+use ggez_goodies::scene;
+use std::fs;
+
+const DEFAULT_LIGHT_RADIUS: f32 = 48.0;
+const LIGHT_RADIUS_STEP: f32 = 8.0;
+const STATUS_MARGIN: f32 = 16.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Brush {
+	Wall,
+	Empty,
+	Door { length: usize, horizontal: bool },
+	DoorShard,
+	LightShard,
+	Player,
+}
+
+const BRUSHES: [Brush; 9] = [
+	Brush::Wall,
+	Brush::Empty,
+	Brush::Door { length: 2, horizontal: true },
+	Brush::Door { length: 2, horizontal: false },
+	Brush::Door { length: 3, horizontal: true },
+	Brush::Door { length: 3, horizontal: false },
+	Brush::DoorShard,
+	Brush::LightShard,
+	Brush::Player,
+];
+
+impl Brush {
+	fn name(&self) -> String {
+		match self {
+			Self::Wall => "wall".to_owned(),
+			Self::Empty => "empty".to_owned(),
+			Self::Door { length, horizontal: true } => format!("horizontal {}-tile door", length),
+			Self::Door { length, horizontal: false } => format!("vertical {}-tile door", length),
+			Self::DoorShard => "door shard".to_owned(),
+			Self::LightShard => "light shard".to_owned(),
+			Self::Player => "player start".to_owned(),
+		}
+	}
+}
+
+pub struct EditorScene {
+	name: String,
+	level: Level,
+
+	cursor: (usize, usize),
+	brush_n: usize,
+	light_radius: f32,
+	status: String,
+
+	quit: bool,
+	playtest: bool,
+}
+
+impl EditorScene {
+	/// Opens `/levels/{name}.toml`, or creates a new level of the given
+	/// size and wall tileset if there's no such file yet.
+	pub fn new(
+		_world: &mut World,
+		context: &mut ggez::Context,
+		name: &str,
+		new_level: Option<(usize, usize, String)>,
+	) -> ggez::GameResult<Self> {
+		let path = format!("/levels/{}.toml", name);
+
+		let level = if ggez::filesystem::exists(context, &path) {
+			Level::load(context, &path)?
+		}
+		else if let Some((width, height, key)) = new_level {
+			Self::empty_level(width, height, &key)
+		}
+		else {
+			return Err(ggez::GameError::ResourceLoadError(format!(
+				"Level {} doesn't exist, give its width, height and wall tileset to create it!", name
+			)));
+		};
+
+		let cursor = (level.player_x as usize, level.player_y as usize);
+
+		Ok(Self {
+			name: name.to_owned(),
+			level,
+
+			cursor,
+			brush_n: 0,
+			light_radius: DEFAULT_LIGHT_RADIUS,
+			status: String::new(),
+
+			quit: false,
+			playtest: false,
+		})
+	}
+
+	/// `--editor <name> [<width> <height> <key>]`
+	pub fn from_args(world: &mut World, context: &mut ggez::Context, args: &[String]) -> ggez::GameResult<Self> {
+		let name = args.get(0)
+			.ok_or_else(|| ggez::GameError::ResourceLoadError("--editor expects a level name!".to_owned()))?;
+
+		let new_level = match (args.get(1), args.get(2), args.get(3)) {
+			(Some(width), Some(height), Some(key)) => match (width.parse::<usize>(), height.parse::<usize>()) {
+				(Ok(width), Ok(height)) if width > 0 && height > 0 => Some((width, height, key.to_owned())),
+				_ => return Err(ggez::GameError::ResourceLoadError(format!(
+					"--editor expects a positive width and height, got {} and {}!", width, height
+				))),
+			},
+			_ => None,
+		};
+
+		Self::new(world, context, name, new_level)
+	}
+
+	/// Solid border, empty inside.
+	fn empty_level(width: usize, height: usize, key: &str) -> Level {
+		let mut level = Level::new(width, height, key);
+
+		for y in 0..height {
+			for x in 0..width {
+				if x == 0 || y == 0 || x + 1 == width || y + 1 == height {
+					level.set(x, y, Wall::S);
+				}
+			}
+		}
+
+		level
+	}
+
+	fn brush(&self) -> Brush {
+		BRUSHES[self.brush_n]
+	}
+
+	fn entity_at(&self, x: usize, y: usize) -> Option<usize> {
+		self.level.entities.iter()
+			.position(|entity| entity.x as usize == x && entity.y as usize == y)
+	}
+
+	fn remove_entity(&mut self, x: usize, y: usize) {
+		if let Some(index) = self.entity_at(x, y) {
+			let entity = self.level.entities.remove(index);
+
+			if let PickUpEffect::ActivateDoors = entity.effect {
+				self.level.shards_for_door_activation = self.level.shards_for_door_activation.saturating_sub(1);
+			}
+		}
+	}
+
+	/// Sets a single tile, turning the rest of a door it overwrites into
+	/// walls, so there are never any half doors left behind.
+	fn set_wall(&mut self, x: usize, y: usize, wall: Wall) {
+		if let Some(part) = self.level.get(x, y).door_part() {
+			for index in 0..part.length {
+				let (door_x, door_y) = if part.horizontal {
+					(x as isize + index as isize - part.index as isize, y as isize)
+				}
+				else {
+					(x as isize, y as isize + index as isize - part.index as isize)
+				};
+
+				if door_x < 0 || door_y < 0 || door_x >= self.level.width as isize || door_y >= self.level.height as isize {
+					continue;
+				}
+
+				let (door_x, door_y) = (door_x as usize, door_y as usize);
+
+				if self.level.get(door_x, door_y).door_part() == Some(DoorPart { index, ..part }) {
+					self.level.set(door_x, door_y, Wall::S);
+				}
+			}
+		}
+
+		if !wall.is_empty() {
+			self.remove_entity(x, y);
+		}
+
+		self.level.set(x, y, wall);
+	}
+
+	/// Places a whole door starting at the cursor, going right or down.
+	fn place_door(&mut self, length: usize, horizontal: bool) {
+		let (x, y) = self.cursor;
+
+		let fits = if horizontal {
+			x + length <= self.level.width
+		}
+		else {
+			y + length <= self.level.height
+		};
+
+		if !fits {
+			self.status = "The door doesn't fit here!".to_owned();
+			return;
+		}
+
+		for index in 0..length {
+			let wall = Wall::from_door_part(DoorPart { index, length, horizontal })
+				.expect("Invalid door length!");
+
+			if horizontal {
+				self.set_wall(x + index, y, wall);
+			}
+			else {
+				self.set_wall(x, y + index, wall);
+			}
+		}
+	}
+
+	fn place_entity(&mut self, effect: PickUpEffect) {
+		let (x, y) = self.cursor;
+
+		self.remove_entity(x, y);
+
+		if !self.level.get(x, y).is_empty() {
+			self.set_wall(x, y, Wall::N);
+		}
+
+		if let PickUpEffect::ActivateDoors = effect {
+			self.level.shards_for_door_activation += 1;
+		}
+
+		let entity_types = [
+			EntityType::Shard0,
+			EntityType::Shard1,
+			EntityType::Shard2,
+			EntityType::Shard3,
+			EntityType::Shard4,
+		];
+
+		self.level.entities.push(Entity {
+			entity_type: entity_types[self.level.entities.len() % entity_types.len()].clone(),
+			x: x as f32 + 0.5,
+			y: y as f32 + 0.5,
+			light_radius: self.light_radius,
+			effect,
+		});
+	}
+
+	fn paint(&mut self) {
+		let (x, y) = self.cursor;
+
+		match self.brush() {
+			Brush::Wall => self.set_wall(x, y, Wall::S),
+			Brush::Empty => {
+				self.set_wall(x, y, Wall::N);
+				self.remove_entity(x, y);
+			},
+			Brush::Door { length, horizontal } => self.place_door(length, horizontal),
+			Brush::DoorShard => self.place_entity(PickUpEffect::ActivateDoors),
+			Brush::LightShard => self.place_entity(PickUpEffect::IncreasePlayerLightRadius),
+			Brush::Player => {
+				if !self.level.get(x, y).is_empty() {
+					self.set_wall(x, y, Wall::N);
+				}
+
+				self.level.player_x = x as f32 + 0.5;
+				self.level.player_y = y as f32 + 0.5;
+			},
+		}
+	}
+
+	/// Changes the radius for new shards and the shard under the cursor.
+	fn change_light_radius(&mut self, delta: f32) {
+		self.light_radius = (self.light_radius + delta).max(0.0);
+
+		if let Some(index) = self.entity_at(self.cursor.0, self.cursor.1) {
+			self.level.entities[index].light_radius = self.light_radius;
+		}
+	}
+
+	fn move_cursor(&mut self, dx: isize, dy: isize) {
+		let x = (self.cursor.0 as isize + dx).max(0).min(self.level.width as isize - 1);
+		let y = (self.cursor.1 as isize + dy).max(0).min(self.level.height as isize - 1);
+
+		self.cursor = (x as usize, y as usize);
+	}
+
+	fn save(&mut self, world: &mut World) {
+		let path = world.resource_path.join("levels").join(format!("{}.toml", self.name));
+
+		// levels drawn as maps stay that way
+		let content = if self.level.uses_map {
+			ascii_map::to_toml(&self.level)
+		}
+		else {
+			toml::to_string(&self.level).map_err(|e| e.to_string())
+		};
+
+		let result = content
+			.and_then(|content| fs::write(&path, content).map_err(|e| e.to_string()));
+
+		self.status = match result {
+			Ok(()) => {
+				let mut diagnostics = validation::validate(&self.level);
+				diagnostics.extend(validation::validate_surfaces(&self.level, &world.movement));
+
+				for diagnostic in diagnostics.iter() {
+					warn!("{:?}: {}", path, diagnostic);
+				}
+
+				format!("Saved {:?} with {} warnings.", path, diagnostics.len())
+			},
+			Err(e) => format!("Couldn't save {:?}: {}", path, e),
+		};
+
+		info!("{}", self.status);
+	}
+
+	fn get_level_offset(&self, world: &mut World) -> Point2 {
+		self.level.get_offset(world.center(), (WALL_SIZE, WALL_SIZE))
+	}
+
+	fn draw_level(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
+		let offset = self.get_level_offset(world);
+		let level = &self.level;
+
+		let tile_rect = |x: f32, y: f32, size: f32| {
+			graphics::Rect::new(
+				offset.x + x * WALL_SIZE - size / 2.0,
+				offset.y + y * WALL_SIZE - size / 2.0,
+				size,
+				size,
+			)
+		};
+
+		let mut builder = graphics::MeshBuilder::new();
+
+		for y in 0..level.height {
+			for x in 0..level.width {
+				let wall = level.get(x, y);
+
+				let color = if wall.is_door() {
+					graphics::Color::new(0.9, 0.5, 0.2, 1.0)
+				}
+				else if wall.is_wall() {
+					graphics::Color::new(0.6, 0.6, 0.6, 1.0)
+				}
+				else {
+					graphics::Color::new(0.1, 0.1, 0.1, 1.0)
+				};
+
+				// one pixel gap keeps the grid visible
+				let rect = tile_rect(x as f32 + 0.5, y as f32 + 0.5, WALL_SIZE - 1.0);
+				builder.rectangle(graphics::DrawMode::fill(), rect, color);
+			}
+		}
+
+		for entity in level.entities.iter() {
+			let color = match entity.effect {
+				PickUpEffect::ActivateDoors => graphics::Color::new(0.4, 0.8, 1.0, 1.0),
+				PickUpEffect::IncreasePlayerLightRadius => graphics::Color::new(1.0, 1.0, 1.0, 1.0),
+			};
+
+			let center = Point2::new(offset.x + entity.x * WALL_SIZE, offset.y + entity.y * WALL_SIZE);
+
+			builder.rectangle(graphics::DrawMode::fill(), tile_rect(entity.x, entity.y, WALL_SIZE / 2.0), color);
+
+			if entity.light_radius > 0.0 {
+				builder.circle(graphics::DrawMode::stroke(1.0), center, entity.light_radius, 0.5, color);
+			}
+		}
+
+		builder.rectangle(
+			graphics::DrawMode::fill(),
+			tile_rect(level.player_x, level.player_y, PLAYER_WIDTH),
+			graphics::Color::new(1.0, 0.9, 0.2, 1.0),
+		);
+
+		builder.rectangle(
+			graphics::DrawMode::stroke(2.0),
+			tile_rect(self.cursor.0 as f32 + 0.5, self.cursor.1 as f32 + 0.5, WALL_SIZE),
+			graphics::WHITE,
+		);
+
+		let mesh = builder.build(context)?;
+		graphics::draw(context, &mesh, graphics::DrawParam::default())
+	}
+
+	fn draw_status(&self, context: &mut ggez::Context) -> ggez::GameResult<()> {
+		let text = graphics::Text::new(format!(
+			"{} | brush: {} | cursor: {}, {} | light radius: {} | shards needed: {} | {}",
+			self.name,
+			self.brush().name(),
+			self.cursor.0,
+			self.cursor.1,
+			self.light_radius,
+			self.level.shards_for_door_activation,
+			self.status,
+		));
+
+		graphics::draw(
+			context,
+			&text,
+			graphics::DrawParam::default()
+				.dest(Point2::new(STATUS_MARGIN, STATUS_MARGIN))
+		)
+	}
+}
+
+impl scene::Scene<World, input::Event> for EditorScene {
+	fn update(&mut self, world: &mut World, context: &mut ggez::Context) -> scenes::Switch {
+		if self.quit {
+			self.quit = false;
+			world.exit = true;
+
+			return scene::SceneSwitch::None;
+		}
+
+		if self.playtest {
+			self.playtest = false;
+
+			match LabyrinthScene::playtest(world, context, self.level.clone()) {
+				Ok(scene) => return scene::SceneSwitch::push(scene),
+				Err(e) => self.status = format!("Can't playtest: {}", e),
+			}
+		}
+
+		scene::SceneSwitch::None
+	}
+
+	fn draw(&mut self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
+		self.draw_level(world, context)?;
+		self.draw_status(context)?;
+
+		Ok(())
+	}
+
+	fn name(&self) -> &str {
+		"EditorScene"
+	}
+
+	fn input(&mut self, world: &mut World, ev: input::Event, started: bool) {
+		// act on the event itself, button state stays pressed for all
+		// events until the next update
+		let button = match ev {
+			InputEffect::Button(button) if started => button,
+			_ => return,
+		};
+
+		match button {
+			input::Button::Quit => self.quit = true,
+
+			input::Button::Left => self.move_cursor(-1, 0),
+			input::Button::Right => self.move_cursor(1, 0),
+			input::Button::Up => self.move_cursor(0, -1),
+			input::Button::Down => self.move_cursor(0, 1),
+
+			input::Button::NextBrush => self.brush_n = (self.brush_n + 1) % BRUSHES.len(),
+			input::Button::Next => self.paint(),
+
+			input::Button::Decrease => self.change_light_radius(-LIGHT_RADIUS_STEP),
+			input::Button::Increase => self.change_light_radius(LIGHT_RADIUS_STEP),
+
+			input::Button::Save => self.save(world),
+			input::Button::Playtest => self.playtest = true,
+
+			_ => {},
+		}
+	}
+}
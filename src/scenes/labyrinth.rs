@@ -1,116 +1,176 @@
+use crate::generator::{self, GeneratorOptions};
+use crate::ghost::{Ghost, GhostFrame};
 use crate::input;
 use crate::level_configuration::{LevelConfiguration};
-use crate::lighting::{TileLightTracing};
+use crate::lighting::{ExploredTiles, TileLightTracing};
 use crate::resources;
 use crate::scenes;
-use crate::types::{Point2, Rect, Vector2};
+use crate::simulation::{self, Simulation, PLAYER_HEIGHT, PLAYER_WIDTH, WALL_SIZE};
+use crate::snapshot::Snapshot;
+use crate::types::{Point2, Vector2};
 use crate::util;
+use crate::validation;
+use crate::visibility::{self, LightSource};
 use crate::world::World;
 use ggez::audio::SoundSource;
 use ggez::graphics;
-use ggez::timer;
 use ggez;
 use ggez_goodies::scene;
-use log::{debug, info, warn};
+use log::{info, warn};
 // use specs::{self, Join};
 use std::f32::consts::PI;
 use warmy;
 
-const WALL_SIZE: f32 = 32.0;
-const PLAYER_WIDTH: f32 = 16.0;
-const PLAYER_HEIGHT: f32 = 16.0;
-const PLAYER_MAX_SPEED: f32 = 5.0 * 60.0;
-const PLAYER_MAX_ACCELERATION: f32 = 5.0 * 60.0;
-const PLAYER_ACCELERATION_CONST: f32 = 2.0 * 60.0;
-const PLAYER_COLLISION_STEPS: usize = 4;
-const PLAYER_LIGHT_RADIUS: f32 = 100.0;
-
-const RAY_COUNT: usize = 360;
-const STEP_DISTANCE: f32 = 8.0;
+const REMEMBERED_WALL_ALPHA: f32 = 0.25;
+const GHOST_ALPHA: f32 = 0.35;
+const MINIMAP_TILE_SIZE: f32 = 4.0;
+const MINIMAP_MARGIN: f32 = 16.0;
+/// Light of open doors, so the way out is easy to spot.
+const DOOR_LIGHT_RADIUS: f32 = 64.0;
+const DOOR_LIGHT_COLOR: [f32; 3] = [1.0, 0.85, 0.5];
 
 pub struct LabyrinthScene {
 	quit: bool,
 
-	level: warmy::Res<resources::Level>,
+	level: Option<warmy::Res<resources::Level>>,
 	level_configuration: LevelConfiguration,
 
 	player_image: warmy::Res<resources::Image>,
 
 	tiles: resources::TilePack,
 
-	player_coords: Point2,
-	player_speed: f32,
-	player_acceleration: f32,
 	player_direction: Vector2,
-	player_light_radius: f32,
-
-	shards_collected: usize,
-	are_doors_activated: bool,
-	entered_door: bool,
-	entities_visibility: Vec<bool>,
+	/// Player coordinates before the last update, drawing goes from
+	/// there to the current ones.
+	previous_player_coords: Point2,
+
+	simulation: Simulation,
+
+	lit_tiles: Vec<TileLightTracing>,
+	explored: ExploredTiles,
+	entities_seen: Vec<bool>,
+	show_minimap: bool,
+	should_toggle_minimap: bool,
+	/// Seconds spent in the level, for the save's stats.
+	time: f32,
+	should_quicksave: bool,
+	should_quickload: bool,
+
+	/// Transition the ghosts are stored under, only levels from the
+	/// transition list have one.
+	ghost_name: Option<String>,
+	/// Best run so far.
+	ghost: Option<Ghost>,
+	/// This run, dropped if it stops counting (reloads, quickloads).
+	run: Option<Vec<GhostFrame>>,
+	should_toggle_ghost: bool,
+
+	/// Started from the editor, entering a door goes back to it.
+	is_playtest: bool,
+	/// `content_hash` of the last version of `level` that was looked at.
+	level_hash: u64,
 
 	dispatcher: specs::Dispatcher<'static, 'static>,
 }
 
 impl LabyrinthScene {
-	pub fn new(world: &mut World, context: &mut ggez::Context, level_name: &str) -> Self {
+	pub fn new(world: &mut World, context: &mut ggez::Context, level_name: &str) -> ggez::GameResult<Self> {
 		// TODO: Don't use paths here.
 
+		let level_path = resources::Level::find_path(context, level_name);
 		let level = world.resources
-			.get::<resources::Level>(&resources::ResourceKey::from_path(&format!("/levels/{}.toml", level_name)), context)
-			.unwrap();
-		let level_configuration = LevelConfiguration::new(&level.borrow(), resources::TILE_COUNT, resources::CORNER_COUNT);
+			.get::<resources::Level>(&resources::ResourceKey::from_path(&level_path), context)
+			.map_err(|e| ggez::GameError::ResourceLoadError(format!("{:?}", e)))?;
+		let level_data = level.borrow().clone();
 
-		let player_image = world.resources
-			.get::<resources::Image>(&resources::ResourceKey::from_path("/images/character-16x16.png"), context)
-			.unwrap();
+		let mut scene = Self::from_level(world, context, Some(level), level_data)?;
+		scene.ghost = world.ghosts.get(level_name, scene.level_hash).cloned();
+		scene.ghost_name = Some(level_name.to_owned());
+		scene.run = Some(Vec::new());
 
-		let tiles = resources::TilePack::load(world, context, &level.borrow().key);
-		let offset = level.borrow().get_offset(world.center(), (WALL_SIZE, WALL_SIZE));
+		Ok(scene)
+	}
 
-		let player_coords = Point2::new(
-			level.borrow().player_x * WALL_SIZE + offset.x,
-			level.borrow().player_y * WALL_SIZE + offset.y,
-		);
-		let player_light_radius = level.borrow().player_light_radius;
+	pub fn generated(world: &mut World, context: &mut ggez::Context, options: &GeneratorOptions) -> ggez::GameResult<Self> {
+		let mut level = generator::generate(options);
+		level.update_content_hash();
 
-		let are_doors_activated = level.borrow().shards_for_door_activation == 0;
+		Self::from_level(world, context, None, level)
+	}
 
-		let entities_visibility = {
-			let mut ret = Vec::new();
+	pub fn playtest(world: &mut World, context: &mut ggez::Context, mut level: resources::Level) -> ggez::GameResult<Self> {
+		level.update_content_hash();
 
-			for _ in level.borrow().entities.iter() {
-				ret.push(true);
-			}
+		let mut scene = Self::from_level(world, context, None, level)?;
+		scene.is_playtest = true;
 
-			ret
-		};
+		Ok(scene)
+	}
+
+	/// `resource` is only there for levels loaded from files.
+	fn from_level(
+		world: &mut World,
+		context: &mut ggez::Context,
+		resource: Option<warmy::Res<resources::Level>>,
+		level: resources::Level,
+	) -> ggez::GameResult<Self> {
+		if !ggez::filesystem::exists(context, &format!("/images/walls/{}", level.key)) {
+			return Err(ggez::GameError::ResourceLoadError(format!("There's no wall tileset {}!", level.key)));
+		}
 
-		let mut dispatcher = Self::register_systems();
+		let level_configuration = LevelConfiguration::new(&level, resources::TILE_COUNT, resources::CORNER_COUNT, world.seed);
 
-		Self {
+		let player_image = world.resources
+			.get::<resources::Image>(&resources::ResourceKey::from_path("/images/character-16x16.png"), context)
+			.unwrap();
+
+		let tiles = resources::TilePack::load(world, context, &level.key);
+
+		for diagnostic in validation::validate_surfaces(&level, &world.movement) {
+			warn!("{}", diagnostic);
+		}
+
+		let level_hash = level.content_hash;
+		let simulation = Simulation::new(level, &world.movement);
+		let explored = ExploredTiles::new(simulation.level.walls.len());
+		let entities_seen = vec![false; simulation.level.entities.len()];
+
+		let dispatcher = Self::register_systems();
+
+		Ok(Self {
 			quit: false,
 
-			level,
+			level: resource,
 			level_configuration,
 
 			player_image,
 
 			tiles,
 
-			player_coords,
-			player_speed: 0.0,
-			player_acceleration: 0.0,
 			player_direction: Vector2::zero(),
-			player_light_radius,
+			previous_player_coords: simulation.player_coords,
+
+			simulation,
+
+			lit_tiles: Vec::new(),
+			explored,
+			entities_seen,
+			show_minimap: false,
+			should_toggle_minimap: false,
+			time: 0.0,
+			should_quicksave: false,
+			should_quickload: false,
 
-			shards_collected: 0,
-			are_doors_activated,
-			entered_door: false,
-			entities_visibility,
+			ghost_name: None,
+			ghost: None,
+			run: None,
+			should_toggle_ghost: false,
+
+			is_playtest: false,
+			level_hash,
 
 			dispatcher,
-		}
+		})
 	}
 
 	fn register_systems() -> specs::Dispatcher<'static, 'static> {
@@ -119,325 +179,351 @@ impl LabyrinthScene {
 			.build()
 	}
 
-	fn get_level_offset(&self, world: &mut World) -> Point2 {
-		self.level.borrow().get_offset(world.center(), (WALL_SIZE, WALL_SIZE))
-	}
-
-	fn draw_light(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
-		// select tiles that are in player's radius
-		let mut target_tiles = {
-			let offset = self.get_level_offset(world);
-			let level = &self.level.borrow();
-
-			let mut tiles = Vec::new();
-
-			for i in 0..level.width {
-				for j in 0..level.height {
-					if level.get(i, j).is_door() {
-						if self.are_doors_activated {
-							continue;
-						}
-					}
-					else if level.get(i, j).is_empty() {
-						continue;
-					}
-
-					let tile_id = j * level.width + i;
-
-					let tile_position = Point2::new(
-						offset.x + i as f32 * WALL_SIZE + WALL_SIZE / 2.0,
-						offset.y + j as f32 * WALL_SIZE + WALL_SIZE / 2.0,
-					);
-
-					let distance = util::get_distance(tile_position, self.player_coords);
-
-					if distance <= self.player_light_radius + WALL_SIZE {
-						tiles.push(TileLightTracing::new(tile_id, tile_position, WALL_SIZE, WALL_SIZE));
-					}
-				}
-			}
-
-			tiles
+	/// Picks up changes to the level file made while playing, once
+	/// `Store::sync` has reloaded it.
+	fn reload_level(&mut self, world: &mut World, context: &mut ggez::Context) {
+		let level = match &self.level {
+			Some(level) if level.borrow().content_hash != self.level_hash => level.borrow().clone(),
+			_ => return,
 		};
 
-		// render all tiles in radius, for test
-		if false {
-			for tile in target_tiles.iter() {
-				graphics::draw(
-					context,
-					&self.tiles.tile_up[0].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(tile.rect.x, tile.rect.y))
-				)?;
+		self.level_hash = level.content_hash;
 
-				graphics::draw(
-					context,
-					&self.tiles.tile_down[0].borrow().0,
-					graphics::DrawParam::default()
-						.dest(Point2::new(tile.rect.x, tile.rect.y))
-				)?;
-			}
+		// the old ghost and this run were on another version
+		if let Some(name) = &self.ghost_name {
+			self.ghost = world.ghosts.get(name, self.level_hash).cloned();
 		}
+		self.run = None;
 
-		// assume player's position as 0,0 for all tiles
-		TileLightTracing::set_origin(&mut target_tiles, self.player_coords);
+		if level.key != self.simulation.level.key {
+			if !ggez::filesystem::exists(context, &format!("/images/walls/{}", level.key)) {
+				warn!("Reloaded level uses unknown wall tileset {}, keeping the old version.", level.key);
+				return;
+			}
 
-		// start ray tracing
-		use euclid::Angle;
+			self.tiles = resources::TilePack::load(world, context, &level.key);
+		}
 
-		let mut degree = 0.0;
+		for change in describe_changes(&self.simulation.level, &level) {
+			info!("Level reloaded: {}", change);
+		}
 
-		for _ in 0..RAY_COUNT {
-			let (sin, cos) = Angle::degrees(degree).sin_cos();
-			let direction_v = Vector2::new(cos, sin);
+		let is_same_size = level.width == self.simulation.level.width && level.height == self.simulation.level.height;
 
-			let mut step_n = 1;
-			loop {
-				let current_position = direction_v * STEP_DISTANCE * step_n as f32;
-				if current_position.length() > self.player_light_radius {
-					break;
-				}
+		let entities_seen = level.entities.iter()
+			.map(|entity| {
+				self.simulation.level.entities.iter()
+					.zip(self.entities_seen.iter())
+					.any(|(old, &seen)| seen && old.x == entity.x && old.y == entity.y)
+			})
+			.collect();
 
-				if let Some(tile) = TileLightTracing::find_intersection_mut(&mut target_tiles, current_position.to_point()) {
-					tile.register_hit(current_position.to_point());
-					break;
-				}
+		self.level_configuration = LevelConfiguration::new(&level, resources::TILE_COUNT, resources::CORNER_COUNT, world.seed);
+		self.entities_seen = entities_seen;
 
-				step_n += 1;
-			}
+		if !is_same_size {
+			self.explored = ExploredTiles::new(level.walls.len());
+		}
 
-			degree += 360.0 / RAY_COUNT as f32;
+		for diagnostic in validation::validate_surfaces(&level, &world.movement) {
+			warn!("Level reloaded: {}", diagnostic);
 		}
 
-		// update coordinates of all tiles
-		TileLightTracing::set_origin(&mut target_tiles, Point2::new(-self.player_coords.x, -self.player_coords.y));
+		let is_player_kept = self.simulation.reload(level);
+		self.previous_player_coords = self.simulation.player_coords;
 
-		// render
-		if false {
-			for tile in target_tiles.iter() {
-				if tile.hits > 0 {
-					graphics::draw(
-						context,
-						&self.tiles.tile_up[0].borrow().0,
-						graphics::DrawParam::default()
-							.dest(Point2::new(tile.rect.x, tile.rect.y))
-					)?;
+		if !is_player_kept {
+			info!("Level reloaded: player is back at the start");
+		}
+	}
 
-					graphics::draw(
-						context,
-						&self.tiles.tile_down[0].borrow().0,
-						graphics::DrawParam::default()
-							.dest(Point2::new(tile.rect.x, tile.rect.y))
-					)?;
-				}
-			}
+	/// Everything needed to put the level back the way it is now.
+	pub fn snapshot(&self) -> Snapshot {
+		Snapshot {
+			level_hash: self.level_hash,
+			level_seed: self.simulation.level.seed,
+			tile_seed: self.level_configuration.seed,
+			time: self.time,
+
+			player_x: self.simulation.player_coords.x,
+			player_y: self.simulation.player_coords.y,
+			player_velocity_x: self.simulation.player_velocity.x,
+			player_velocity_y: self.simulation.player_velocity.y,
+			player_light_radius: self.simulation.player_light_radius,
+
+			shards_collected: self.simulation.shards_collected,
+			are_doors_activated: self.simulation.are_doors_activated,
+			entities_visibility: self.simulation.entities_visibility.clone(),
+			entities_seen: self.entities_seen.clone(),
+			explored: self.explored.clone(),
 		}
+	}
 
-		if true {
-			for tile in target_tiles.iter() {
-				tile.draw(context, &self.tiles, &self.level_configuration)?;
-			}
+	/// Fails if the snapshot was taken in another level, or another
+	/// version of this one.
+	pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+		let level = &self.simulation.level;
+
+		if snapshot.level_hash != self.level_hash || snapshot.level_seed != level.seed {
+			return Err("Snapshot was taken in a different level!".to_owned());
 		}
 
-		Ok(())
-	}
+		let fits = snapshot.explored.tile_count() == level.walls.len()
+			&& snapshot.entities_visibility.len() == level.entities.len()
+			&& snapshot.entities_seen.len() == level.entities.len();
 
-	fn draw_player(&self, context: &mut ggez::Context) -> ggez::GameResult<()> {
-		let player = &self.player_image.borrow().0;
+		if !fits {
+			return Err("Snapshot doesn't fit the level!".to_owned());
+		}
 
-		let x = self.player_coords.x - PLAYER_WIDTH as f32 / 2.0;
-		let y = self.player_coords.y - PLAYER_HEIGHT as f32 / 2.0;
+		self.level_configuration = LevelConfiguration::new(level, resources::TILE_COUNT, resources::CORNER_COUNT, Some(snapshot.tile_seed));
+		self.time = snapshot.time;
 
-		graphics::draw(
-			context,
-			player,
-			graphics::DrawParam::default()
-				.dest(Point2::new(x, y))
-		)?;
+		self.simulation.player_coords = Point2::new(snapshot.player_x, snapshot.player_y);
+		self.previous_player_coords = self.simulation.player_coords;
+		self.simulation.player_velocity = Vector2::new(snapshot.player_velocity_x, snapshot.player_velocity_y);
+		self.simulation.player_light_radius = snapshot.player_light_radius;
+
+		self.simulation.shards_collected = snapshot.shards_collected;
+		self.simulation.are_doors_activated = snapshot.are_doors_activated;
+		self.simulation.entered_door = None;
+		self.simulation.entities_visibility = snapshot.entities_visibility.clone();
+		self.entities_seen = snapshot.entities_seen.clone();
+		self.explored = snapshot.explored.clone();
+		self.run = None;
 
 		Ok(())
 	}
 
-	fn get_tile_id(&self, world: &mut World, screen_coords: Point2) -> Option<usize> {
-		let offset = self.get_level_offset(world);
-		let point = screen_coords - offset;
+	fn quicksave(&self, context: &ggez::Context) {
+		let path = Snapshot::quicksave_path(context);
 
-		let x = (point.x / WALL_SIZE) as isize;
-		let y = (point.y / WALL_SIZE) as isize;
+		match self.snapshot().write(&path) {
+			Ok(()) => info!("Quicksaved to {:?}", path),
+			Err(e) => warn!("Couldn't quicksave to {:?}: {}", path, e),
+		}
+	}
 
-		let level = &self.level.borrow();
-		let tile_id = level.width as isize * y + x;
+	fn quickload(&mut self, context: &ggez::Context) {
+		let path = Snapshot::quicksave_path(context);
 
-		if tile_id >= 0 && tile_id < level.walls.len() as isize {
-			Some(tile_id as usize)
-		}
-		else {
-			None
+		match Snapshot::load(&path).and_then(|snapshot| self.restore(&snapshot)) {
+			Ok(()) => info!("Quickloaded from {:?}", path),
+			Err(e) => warn!("Couldn't quickload from {:?}: {}", path, e),
 		}
 	}
 
-	fn get_tile(&self, tile_id: usize) -> resources::Wall {
-		self.level.borrow().walls[tile_id].clone()
+	/// Keeps this run as the ghost if it's the fastest one.
+	fn offer_ghost(&mut self, world: &mut World, context: &ggez::Context) {
+		let (name, frames) = match (&self.ghost_name, self.run.take()) {
+			(Some(name), Some(frames)) => (name, frames),
+			_ => return,
+		};
+
+		let ghost = Ghost {
+			level_hash: self.level_hash,
+			time: self.time,
+			frames,
+		};
+
+		if world.ghosts.offer(name, ghost) {
+			info!("New best time for {}: {:.2}s", name, self.time);
+			world.save_ghosts(context);
+		}
 	}
 
-	fn move_player(&mut self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
-		let direction = self.player_direction;
+	fn get_level_offset(&self, world: &mut World) -> Point2 {
+		self.simulation.level.get_offset(world.center(), (WALL_SIZE, WALL_SIZE))
+	}
 
-		let dt = timer::duration_to_f64(timer::delta(context)) as f32;
-		// let fps = 1000 / timer::average_delta(context).as_millis();
-		// info!("fps: {}, dt: {}", fps, dt);
+	/// Where the player is drawn, `interpolation` of the way from the
+	/// previous update to the last one.
+	fn get_player_coords(&self, world: &mut World, interpolation: f32) -> Point2 {
+		let moved = (self.simulation.player_coords - self.previous_player_coords) * interpolation;
 
-		if direction.length() > 0.0 {
-			// moving
-			self.player_acceleration += PLAYER_ACCELERATION_CONST;
+		self.previous_player_coords + moved + self.get_level_offset(world).to_vector()
+	}
 
-			if self.player_acceleration > PLAYER_MAX_ACCELERATION {
-				self.player_acceleration = PLAYER_MAX_ACCELERATION;
-			}
+	fn get_light_sources(&self) -> Vec<LightSource> {
+		let level = &self.simulation.level;
+		let mut lights = Vec::new();
 
-			self.player_speed += self.player_acceleration;
+		lights.push(LightSource::new(
+			self.simulation.player_coords,
+			self.simulation.player_light_radius,
+			graphics::WHITE,
+		));
 
-			if self.player_speed > PLAYER_MAX_SPEED {
-				self.player_speed = PLAYER_MAX_SPEED;
+		for (index, entity) in level.entities.iter().enumerate() {
+			if !self.simulation.entities_visibility[index] {
+				continue;
 			}
 
-			let movement_v = Vector2::new(direction.x * self.player_speed, direction.y * self.player_speed) * dt;
+			lights.push(LightSource::new(
+				Simulation::entity_position(entity),
+				entity.light_radius,
+				graphics::WHITE,
+			));
+		}
 
-			self.move_player_with_collisions(world, context, movement_v)?;
+		for light in level.lights.iter() {
+			lights.push(LightSource::new(
+				Point2::new(light.x * WALL_SIZE, light.y * WALL_SIZE),
+				light.radius,
+				graphics::Color::new(light.color[0], light.color[1], light.color[2], 1.0),
+			));
 		}
-		else {
-			// not moving
-			// TODO: slowing down?
 
-			self.player_acceleration = 0.0;
-			self.player_speed = 0.0;
+		if self.simulation.are_doors_activated {
+			for door in self.simulation.doors.iter() {
+				let rect = door.rect(WALL_SIZE);
+
+				lights.push(LightSource::new(
+					Point2::new(rect.x + rect.w / 2.0, rect.y + rect.h / 2.0),
+					DOOR_LIGHT_RADIUS,
+					graphics::Color::new(DOOR_LIGHT_COLOR[0], DOOR_LIGHT_COLOR[1], DOOR_LIGHT_COLOR[2], 1.0),
+				));
+			}
 		}
 
-		Ok(())
+		lights
 	}
 
-	fn pick_up_items(&mut self, world: &mut World, _context: &mut ggez::Context) -> ggez::GameResult<()> {
+	fn update_light(&mut self) {
+		let level = &self.simulation.level;
+		let are_doors_activated = self.simulation.are_doors_activated;
+
+		// the player's light is traced on its own to know what goes on the minimap
+		let lights = self.get_light_sources();
+		let player_tiles = visibility::trace_light(level, WALL_SIZE, &lights[0], are_doors_activated);
+
+		self.explored.map(&player_tiles);
+
+		self.lit_tiles = visibility::trace_lights(level, WALL_SIZE, &lights[1..], are_doors_activated);
+		visibility::merge_tiles(&mut self.lit_tiles, player_tiles);
+
+		self.explored.update(&self.lit_tiles);
+
+		for (index, entity) in self.simulation.level.entities.iter().enumerate() {
+			if !self.entities_seen[index] && self.is_entity_lit(entity) {
+				self.entities_seen[index] = true;
+			}
+		}
+	}
+
+	fn draw_remembered_walls(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
 		let offset = self.get_level_offset(world);
-		let level = &self.level.borrow();
+		let level = &self.simulation.level;
+		let color = graphics::Color::new(1.0, 1.0, 1.0, REMEMBERED_WALL_ALPHA);
 
-		for (index, entity) in level.entities.iter().enumerate() {
-			if !self.entities_visibility[index] {
+		for (tile_id, shapes) in self.explored.iter() {
+			if level.walls[tile_id].is_door() && self.simulation.are_doors_activated {
 				continue;
 			}
 
-			let position = Point2::new(
-				entity.x * WALL_SIZE + offset.x,
-				entity.y * WALL_SIZE + offset.y,
+			let rect = graphics::Rect::new(
+				offset.x + (tile_id % level.width) as f32 * WALL_SIZE,
+				offset.y + (tile_id / level.width) as f32 * WALL_SIZE,
+				WALL_SIZE,
+				WALL_SIZE,
 			);
 
-			let distance = util::get_distance(position, self.player_coords);
-
-			if distance < (WALL_SIZE + PLAYER_WIDTH) / 2.0 {
-				match entity.effect {
-					resources::PickUpEffect::IncreasePlayerLightRadius => {
-						self.player_light_radius = PLAYER_LIGHT_RADIUS;
-					},
-					resources::PickUpEffect::ActivateDoors => {
-						self.shards_collected += 1;
-
-						if self.shards_collected >= level.shards_for_door_activation {
-							self.are_doors_activated = true;
-						}
-					},
-				}
-
-				self.entities_visibility[index] = false;
-				let _ = world.sound_pick_up.play_detached();
+			for state in shapes {
+				TileLightTracing::draw_state(context, &self.tiles, &self.level_configuration, tile_id, rect, state, color)?;
 			}
 		}
 
 		Ok(())
 	}
 
-	fn move_player_with_collisions(&mut self, world: &mut World, context: &mut ggez::Context, movement_v: Vector2) -> ggez::GameResult<()> {
-		let mut current = Rect::new(
-			self.player_coords.x - PLAYER_WIDTH / 2.0,
-			self.player_coords.y - PLAYER_HEIGHT / 2.0,
-			PLAYER_WIDTH,
-			PLAYER_HEIGHT,
-		);
-
-		if movement_v.x != 0.0 {
-			let mut possible = current;
-
-			for _ in 0..PLAYER_COLLISION_STEPS {
-				possible.x += movement_v.x / PLAYER_COLLISION_STEPS as f32;
+	fn draw_light(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
+		let offset = self.get_level_offset(world);
 
-				if !self.check_wall_collision(world, context, possible) {
-					current.x = possible.x;
-				}
-			}
+		if self.simulation.level.remember_walls {
+			self.draw_remembered_walls(world, context)?;
 		}
 
-		if movement_v.y != 0.0 {
-			let mut possible = current;
+		let mut target_tiles = self.lit_tiles.clone();
 
-			for _ in 0..PLAYER_COLLISION_STEPS {
-				possible.y += movement_v.y / PLAYER_COLLISION_STEPS as f32;
+		// move tiles from level to screen coordinates
+		TileLightTracing::set_origin(&mut target_tiles, Point2::new(-offset.x, -offset.y));
 
-				if !self.check_wall_collision(world, context, possible) {
-					current.y = possible.y;
+		// render
+		if false {
+			for tile in target_tiles.iter() {
+				if tile.hits > 0 {
+					graphics::draw(
+						context,
+						&self.tiles.tile_up[0].borrow().0,
+						graphics::DrawParam::default()
+							.dest(Point2::new(tile.rect.x, tile.rect.y))
+					)?;
+
+					graphics::draw(
+						context,
+						&self.tiles.tile_down[0].borrow().0,
+						graphics::DrawParam::default()
+							.dest(Point2::new(tile.rect.x, tile.rect.y))
+					)?;
 				}
 			}
 		}
 
-		self.player_coords.x = current.x + PLAYER_WIDTH / 2.0;
-		self.player_coords.y = current.y + PLAYER_HEIGHT / 2.0;
+		if true {
+			for tile in target_tiles.iter() {
+				tile.draw(context, &self.tiles, &self.level_configuration)?;
+			}
+		}
 
 		Ok(())
 	}
 
-	fn check_wall_collision(&mut self, world: &mut World, context: &mut ggez::Context, object: Rect) -> bool {
-		let o = object;
-
-		let points = vec![
-			Point2::new(o.x, o.y),
-			Point2::new(o.x + o.w, o.y),
-			Point2::new(o.x + o.w, o.y + o.h),
-			Point2::new(o.x, o.y + o.h),
-		];
-
-		for point in points.iter() {
-			if let Some(wall) = self.get_tile_by_point(world, context, *point) {
-				if wall.is_door() {
-					if self.are_doors_activated {
-						self.entered_door = true;
-						let _ = world.sound_door.play_detached();
-					}
-					else {
-						return true;
-					}
-				}
-				else if wall.is_wall() {
-					return true;
-				}
-			}
-		}
+	fn draw_player(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
+		let player = &self.player_image.borrow().0;
+		let interpolation = world.interpolation(context);
+		let player_coords = self.get_player_coords(world, interpolation);
+
+		let x = player_coords.x - PLAYER_WIDTH as f32 / 2.0;
+		let y = player_coords.y - PLAYER_HEIGHT as f32 / 2.0;
+
+		graphics::draw(
+			context,
+			player,
+			graphics::DrawParam::default()
+				.dest(Point2::new(x, y))
+		)?;
 
-		false
+		Ok(())
 	}
 
-	fn get_tile_by_point(&mut self, world: &mut World, _context: &mut ggez::Context, point: Point2) -> Option<resources::Wall> {
-		if let Some(tile_id) = self.get_tile_id(world, point) {
-			Some(self.get_tile(tile_id))
-		}
-		else {
-			None
+	fn draw_ghost(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
+		if !world.save.settings.show_ghosts {
+			return Ok(());
 		}
+
+		let time = self.time - (1.0 - world.interpolation(context)) * world.tick;
+
+		let position = match self.ghost.as_ref().and_then(|ghost| ghost.position(time)) {
+			Some(position) => position + self.get_level_offset(world).to_vector(),
+			None => return Ok(()),
+		};
+
+		graphics::draw(
+			context,
+			&self.player_image.borrow().0,
+			graphics::DrawParam::default()
+				.dest(Point2::new(position.x - PLAYER_WIDTH / 2.0, position.y - PLAYER_HEIGHT / 2.0))
+				.color(graphics::Color::new(1.0, 1.0, 1.0, GHOST_ALPHA))
+		)
 	}
 
 	fn draw_doors(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
-		if !self.are_doors_activated {
+		if !self.simulation.are_doors_activated {
 			return Ok(());
 		}
 
 		let offset = self.get_level_offset(world);
-		let level = &self.level.borrow();
+		let level = &self.simulation.level;
 
 		for i in 0..level.width {
 			for j in 0..level.height {
@@ -479,23 +565,36 @@ impl LabyrinthScene {
 		Ok(())
 	}
 
+	/// Within the player's light or its own, with no walls in between.
+	fn is_entity_lit(&self, entity: &resources::Entity) -> bool {
+		let position = Simulation::entity_position(entity);
+		let distance = util::get_distance(position, self.simulation.player_coords);
+
+		if distance > self.simulation.player_light_radius && distance > entity.light_radius {
+			return false;
+		}
+
+		visibility::is_visible(
+			&self.simulation.level,
+			WALL_SIZE,
+			self.simulation.player_coords,
+			position,
+			self.simulation.are_doors_activated,
+		)
+	}
+
 	fn draw_shards(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
 		let offset = self.get_level_offset(world);
-		let level = &self.level.borrow();
+		let level = &self.simulation.level;
 
 		for (index, entity) in level.entities.iter().enumerate() {
-			if !self.entities_visibility[index] {
+			if !self.simulation.entities_visibility[index] {
 				continue;
 			}
 
-			let position = Point2::new(
-				entity.x * WALL_SIZE + offset.x,
-				entity.y * WALL_SIZE + offset.y,
-			);
-
-			let distance = util::get_distance(position, self.player_coords);
+			let position = Simulation::entity_position(entity) + offset.to_vector();
 
-			if distance <= self.player_light_radius || distance <= entity.light_radius {
+			if self.is_entity_lit(entity) {
 				let image = match entity.entity_type {
 					resources::EntityType::Shard0 => &self.tiles.shard_0,
 					resources::EntityType::Shard1 => &self.tiles.shard_1,
@@ -517,6 +616,77 @@ impl LabyrinthScene {
 		Ok(())
 	}
 
+	fn draw_minimap(&self, world: &mut World, context: &mut ggez::Context) -> ggez::GameResult<()> {
+		let level = &self.simulation.level;
+
+		let width = level.width as f32 * MINIMAP_TILE_SIZE;
+		let height = level.height as f32 * MINIMAP_TILE_SIZE;
+		let origin = Point2::new(world.dimensions.0 - width - MINIMAP_MARGIN, MINIMAP_MARGIN);
+
+		let tile_rect = |x: f32, y: f32, size: f32| {
+			graphics::Rect::new(
+				origin.x + x * MINIMAP_TILE_SIZE - size / 2.0,
+				origin.y + y * MINIMAP_TILE_SIZE - size / 2.0,
+				size,
+				size,
+			)
+		};
+
+		let mut builder = graphics::MeshBuilder::new();
+
+		builder.rectangle(
+			graphics::DrawMode::fill(),
+			graphics::Rect::new(origin.x, origin.y, width, height),
+			graphics::Color::new(0.0, 0.0, 0.0, 0.6),
+		);
+
+		for tile_id in (0..level.walls.len()).filter(|&tile_id| self.explored.is_mapped(tile_id)) {
+			let wall = &level.walls[tile_id];
+
+			let color = if wall.is_door() {
+				if self.simulation.are_doors_activated {
+					graphics::Color::new(0.3, 0.9, 0.3, 1.0)
+				}
+				else {
+					graphics::Color::new(0.9, 0.3, 0.3, 1.0)
+				}
+			}
+			else {
+				graphics::Color::new(0.7, 0.7, 0.7, 1.0)
+			};
+
+			let x = (tile_id % level.width) as f32 + 0.5;
+			let y = (tile_id / level.width) as f32 + 0.5;
+
+			builder.rectangle(graphics::DrawMode::fill(), tile_rect(x, y, MINIMAP_TILE_SIZE), color);
+		}
+
+		for (index, entity) in level.entities.iter().enumerate() {
+			if !self.entities_seen[index] {
+				continue;
+			}
+
+			let color = if self.simulation.entities_visibility[index] {
+				graphics::Color::new(0.4, 0.8, 1.0, 1.0)
+			}
+			else {
+				graphics::Color::new(0.4, 0.4, 0.4, 1.0)
+			};
+
+			builder.rectangle(graphics::DrawMode::fill(), tile_rect(entity.x, entity.y, MINIMAP_TILE_SIZE), color);
+		}
+
+		let player_x = self.simulation.player_coords.x / WALL_SIZE;
+		let player_y = self.simulation.player_coords.y / WALL_SIZE;
+
+		builder.rectangle(graphics::DrawMode::fill(), tile_rect(player_x, player_y, MINIMAP_TILE_SIZE), graphics::Color::new(1.0, 0.9, 0.2, 1.0));
+
+		let mesh = builder.build(context)?;
+		graphics::draw(context, &mesh, graphics::DrawParam::default())?;
+
+		Ok(())
+	}
+
 	// fn check_wall_collision_old(&mut self, _world: &mut World, context: &mut ggez::Context, object: Rect) -> ggez::GameResult<bool> {
 	// 	let offset = self.get_level_offset(context);
 
@@ -594,20 +764,131 @@ impl LabyrinthScene {
 	// }
 }
 
+/// Human readable list of what's different between two versions of a level.
+fn describe_changes(old: &resources::Level, new: &resources::Level) -> Vec<String> {
+	let mut changes = Vec::new();
+
+	if old.width != new.width || old.height != new.height {
+		changes.push(format!("size {}x{} -> {}x{}", old.width, old.height, new.width, new.height));
+	}
+	else {
+		let walls = old.walls.iter()
+			.zip(new.walls.iter())
+			.filter(|(old, new)| old != new)
+			.count();
+
+		if walls > 0 {
+			changes.push(format!("{} tiles changed", walls));
+		}
+	}
+
+	if old.key != new.key {
+		changes.push(format!("wall tileset {} -> {}", old.key, new.key));
+	}
+	if old.entities.len() != new.entities.len() {
+		changes.push(format!("{} -> {} entities", old.entities.len(), new.entities.len()));
+	}
+	if old.lights.len() != new.lights.len() {
+		changes.push(format!("{} -> {} lights", old.lights.len(), new.lights.len()));
+	}
+	if old.player_x != new.player_x || old.player_y != new.player_y {
+		changes.push(format!("player start {}, {} -> {}, {}", old.player_x, old.player_y, new.player_x, new.player_y));
+	}
+	if old.shards_for_door_activation != new.shards_for_door_activation {
+		changes.push(format!("shards for doors {} -> {}", old.shards_for_door_activation, new.shards_for_door_activation));
+	}
+	if old.corner_tolerance != new.corner_tolerance {
+		changes.push(format!("corner tolerance {} -> {}", old.corner_tolerance, new.corner_tolerance));
+	}
+
+	if changes.is_empty() {
+		changes.push("no gameplay changes".to_owned());
+	}
+
+	changes
+}
+
 impl scene::Scene<World, input::Event> for LabyrinthScene {
 	fn update(&mut self, world: &mut World, context: &mut ggez::Context) -> scenes::Switch {
+		self.reload_level(world, context);
+
+		// replays mustn't touch the player's quicksave, nor depend on it
+		if self.should_quicksave {
+			self.should_quicksave = false;
+
+			if !world.is_replay {
+				self.quicksave(context);
+			}
+		}
+
+		if self.should_quickload {
+			self.should_quickload = false;
+
+			if world.is_replay {
+				warn!("Ignored a quickload in the replay, the rest of it may not match the recording.");
+			}
+			else {
+				self.quickload(context);
+			}
+		}
+
+		if self.should_toggle_minimap {
+			self.should_toggle_minimap = false;
+			self.show_minimap = !self.show_minimap;
+		}
+
+		if self.should_toggle_ghost {
+			self.should_toggle_ghost = false;
+			world.save.settings.show_ghosts = !world.save.settings.show_ghosts;
+			world.save_progress(context);
+		}
+
 		self.dispatcher.dispatch(&mut world.specs_world);
 
-		self.move_player(world, context)
-			.expect("Failed to move player...");
-		self.pick_up_items(world, context)
-			.expect("Failed to pick up items...");
+		let dt = world.tick;
+		self.time += dt;
+
+		self.previous_player_coords = self.simulation.player_coords;
+
+		for event in self.simulation.update(dt, self.player_direction) {
+			match event {
+				simulation::Event::ItemPickedUp(_) => {
+					let _ = world.sound_pick_up.play_detached();
+				},
+				simulation::Event::DoorEntered(_) => {
+					let _ = world.sound_door.play_detached();
+				},
+				simulation::Event::DoorsActivated => {},
+			}
+		}
+
+		if let Some(run) = &mut self.run {
+			run.push(GhostFrame {
+				time: self.time,
+				x: self.simulation.player_coords.x,
+				y: self.simulation.player_coords.y,
+			});
+		}
+
+		self.update_light();
 
 		if self.quit {
 			scene::SceneSwitch::Pop
 		}
-		else if self.entered_door {
-			world.next_scene(context)
+		else if self.simulation.entered_door.is_some() && self.is_playtest {
+			scene::SceneSwitch::Pop
+		}
+		else if let Some(door) = self.simulation.entered_door {
+			world.record_level(self.time, self.simulation.shards_collected);
+			self.offer_ghost(world, context);
+
+			match self.simulation.level.exit_for(&door) {
+				Some(exit) => {
+					let transition = exit.transition.clone();
+					world.exit_to(context, &transition)
+				},
+				None => world.next_scene(context),
+			}
 		}
 		else {
 			scene::SceneSwitch::None
@@ -619,7 +900,12 @@ impl scene::Scene<World, input::Event> for LabyrinthScene {
 		self.draw_light(world, context)?;
 		self.draw_doors(world, context)?;
 		self.draw_shards(world, context)?;
-		self.draw_player(context)?;
+		self.draw_ghost(world, context)?;
+		self.draw_player(world, context)?;
+
+		if self.show_minimap && !self.simulation.level.hide_minimap {
+			self.draw_minimap(world, context)?;
+		}
 
 		Ok(())
 	}
@@ -634,6 +920,22 @@ impl scene::Scene<World, input::Event> for LabyrinthScene {
 			self.quit = true;
 		}
 
+		if world.input.get_button_pressed(input::Button::Minimap) {
+			self.should_toggle_minimap = true;
+		}
+
+		if world.input.get_button_pressed(input::Button::Ghost) {
+			self.should_toggle_ghost = true;
+		}
+
+		if world.input.get_button_pressed(input::Button::QuickSave) {
+			self.should_quicksave = true;
+		}
+
+		if world.input.get_button_pressed(input::Button::QuickLoad) {
+			self.should_quickload = true;
+		}
+
 		// self.player_coords.x += world.input.get_axis(input::Axis::Horz);
 		// self.player_coords.y -= world.input.get_axis(input::Axis::Vert);
 
@@ -2,6 +2,7 @@ use crate::input;
 use crate::world::World;
 use ggez_goodies::scene;
 
+pub mod editor;
 pub mod labyrinth;
 pub mod transition;
 
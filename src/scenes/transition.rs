@@ -1,15 +1,16 @@
 use crate::input;
 use crate::resources;
 use crate::scenes;
-use crate::types::{Point2};
+use crate::types::{Error, Point2};
 use crate::world::World;
 use ggez::graphics;
-use ggez::timer;
 use ggez;
 use ggez_goodies::scene;
 use serde::{Deserialize};
 use std::path;
 
+const CONTINUE_HINT_MARGIN: f32 = 64.0;
+
 #[derive(Clone, Debug, Deserialize)]
 struct AnimatedScreenInfo {
 	timing: f32,
@@ -30,50 +31,95 @@ impl AnimatedScreenInfo {
 		let list: Self = toml::from_str(&content)
 			.map_err(|e| ggez::error::GameError::ResourceLoadError(e.to_string()))?;
 
+		// there would be nothing to show, nor an end to wait for
+		if list.image_count == 0 {
+			return Err(ggez::error::GameError::ResourceLoadError(format!(
+				"Animated screen {} has no images!", list.folder_name
+			)));
+		}
+
 		Ok(list)
 	}
 }
 
+impl warmy::Load<ggez::Context, resources::ResourceKey> for AnimatedScreenInfo {
+	type Error = Error;
+
+	fn load(
+		key: resources::ResourceKey,
+		_storage: &mut resources::Storage,
+		context: &mut ggez::Context,
+	) -> Result<resources::Loaded<Self>, Self::Error> {
+		debug!("Loading animated screen {:?}", key);
+
+		match key {
+			resources::ResourceKey::Path(path) => {
+				AnimatedScreenInfo::load(context, &path)
+					.map(|x| warmy::Loaded::from(x))
+					.map_err(|e| Error::GgezError(e))
+			},
+		}
+	}
+}
+
+/// Frames are loaded lazily, `info` can change at any time when the
+/// file is edited.
 struct AnimatedScreen {
+	info: warmy::Res<AnimatedScreenInfo>,
 	images: Vec<Option<warmy::Res<resources::Image>>>,
-	timing: f32,
-	looped: bool,
 
-	image_count: usize,
+	/// Folder `images` were loaded from.
 	folder_name: String,
 }
 
 impl AnimatedScreen {
-	fn load<P: AsRef<path::Path>>(_world: &mut World, context: &mut ggez::Context, file: P) -> ggez::GameResult<Option<Self>> {
+	fn load<P: AsRef<path::Path>>(world: &mut World, context: &mut ggez::Context, file: P) -> ggez::GameResult<Option<Self>> {
 		if !ggez::filesystem::exists(context, &file) {
 			return Ok(None)
 		}
 
-		let info = AnimatedScreenInfo::load(context, &file)?;
+		let info = world.resources
+			.get::<AnimatedScreenInfo>(&resources::ResourceKey::from_path(file.as_ref()), context)
+			.map_err(|e| ggez::GameError::ResourceLoadError(format!("{:?}", e)))?;
 
-		let timing = if info.total_time {
-			info.timing / info.image_count as f32
-		}
-		else {
-			info.timing
+		let mut ret = Self {
+			info,
+			images: Vec::new(),
+
+			folder_name: String::new(),
 		};
+		ret.sync();
+
+		Ok(Some(ret))
+	}
 
-		let mut images = Vec::new();
+	/// Drops frames cached for an older version of `info`.
+	fn sync(&mut self) {
+		let info = self.info.borrow();
 
-		for _ in 0..info.image_count {
-			images.push(None);
+		if info.folder_name != self.folder_name || info.image_count != self.images.len() {
+			self.folder_name = info.folder_name.clone();
+			self.images = vec![None; info.image_count];
 		}
+	}
 
-		let ret = Self {
-			images,
-			timing,
-			looped: info.looped,
+	fn timing(&self) -> f32 {
+		let info = self.info.borrow();
 
-			image_count: info.image_count,
-			folder_name: info.folder_name.to_owned(),
-		};
+		if info.total_time {
+			info.timing / info.image_count as f32
+		}
+		else {
+			info.timing
+		}
+	}
 
-		Ok(Some(ret))
+	fn looped(&self) -> bool {
+		self.info.borrow().looped
+	}
+
+	fn image_count(&self) -> usize {
+		self.images.len()
 	}
 }
 
@@ -84,13 +130,13 @@ enum SceneType {
 
 pub struct TransitionScene {
 	is_main: bool,
-	looped: bool,
 
 	scene: SceneType,
 	current_image: usize,
 	extra_dt: f32,
 
 	should_switch_next: bool,
+	should_continue: bool,
 	should_quit: bool,
 }
 
@@ -99,11 +145,7 @@ impl TransitionScene {
 		let animated = AnimatedScreen::load(world, context, &format!("/animated/{}.toml", screen))
 			.expect("Unable to load animated screen!");
 
-		let mut looped = false;
-
 		let scene = if let Some(animated) = animated {
-			looped = animated.looped;
-
 			SceneType::Animated(animated)
 		}
 		else {
@@ -116,33 +158,47 @@ impl TransitionScene {
 
 		Self {
 			is_main,
-			looped,
 
 			scene,
 			current_image: 0,
 			extra_dt: 0.0,
 
 			should_switch_next: false,
+			should_continue: false,
 			should_quit: false,
 		}
 	}
 
 	fn update_frame(&mut self, dt: f32) {
-		if let SceneType::Animated(animated) = &self.scene {
+		if let SceneType::Animated(animated) = &mut self.scene {
+			animated.sync();
+
+			let timing = animated.timing();
+			let image_count = animated.image_count();
+
+			if self.current_image >= image_count {
+				self.current_image = 0;
+			}
+
+			// a broken file shouldn't hang the game
+			if timing <= 0.0 || image_count == 0 {
+				return;
+			}
+
 			self.extra_dt += dt;
 
-			while self.extra_dt > animated.timing {
-				if self.extra_dt - animated.timing < 0.0 {
+			while self.extra_dt > timing {
+				if self.extra_dt - timing < 0.0 {
 					break;
 				}
 
-				self.extra_dt -= animated.timing;
+				self.extra_dt -= timing;
 
 				self.current_image += 1;
-				if self.current_image == animated.image_count {
+				if self.current_image == image_count {
 					self.current_image = 0;
 
-					if !self.looped {
+					if !animated.looped() {
 						self.should_switch_next = true;
 					}
 				}
@@ -182,7 +238,7 @@ impl TransitionScene {
 
 impl scene::Scene<World, input::Event> for TransitionScene {
 	fn update(&mut self, world: &mut World, context: &mut ggez::Context) -> scenes::Switch {
-		let dt = timer::duration_to_f64(timer::delta(context)) as f32;
+		let dt = world.tick;
 		self.update_frame(dt);
 
 		if self.should_switch_next {
@@ -190,6 +246,11 @@ impl scene::Scene<World, input::Event> for TransitionScene {
 
 			world.next_scene(context)
 		}
+		else if self.should_continue {
+			self.should_continue = false;
+
+			world.continue_scene(context)
+		}
 		else if self.should_quit {
 			self.should_quit = false;
 
@@ -243,6 +304,18 @@ impl scene::Scene<World, input::Event> for TransitionScene {
 			}
 		}
 
+		if self.is_main && world.save.transition.is_some() {
+			let text = graphics::Text::new("Press C to continue");
+			let width = text.width(context) as f32;
+
+			graphics::draw(
+				context,
+				&text,
+				graphics::DrawParam::default()
+					.dest(Point2::new(position.x - width / 2.0, world.dimensions.1 - CONTINUE_HINT_MARGIN))
+			)?;
+		}
+
 		Ok(())
 	}
 
@@ -257,5 +330,8 @@ impl scene::Scene<World, input::Event> for TransitionScene {
 		if world.input.get_button_pressed(input::Button::Next) {
 			self.should_switch_next = true;
 		}
+		if self.is_main && world.input.get_button_pressed(input::Button::Continue) {
+			self.should_continue = true;
+		}
 	}
 }
@@ -0,0 +1,767 @@
+//! Gameplay state of a single labyrinth level.
+//!
+//! Nothing in here touches `ggez::Context`, so the whole thing can be
+//! driven from tests or tools without a window or a sound device.
+//! All coordinates are in pixels relative to the level's top left corner.
+use crate::collision;
+use crate::movement::{MovementConfig, MovementModel, Surface};
+use crate::resources::{Door, Entity, Level, PickUpEffect, Wall};
+use crate::types::{Point2, Rect, Vector2};
+use crate::util;
+
+pub const WALL_SIZE: f32 = 32.0;
+pub const PLAYER_WIDTH: f32 = 16.0;
+pub const PLAYER_HEIGHT: f32 = 16.0;
+/// Walls the player can slide along in a single update.
+pub const PLAYER_COLLISION_SLIDES: usize = 3;
+pub const PLAYER_LIGHT_RADIUS: f32 = 100.0;
+pub const PICK_UP_DISTANCE: f32 = (WALL_SIZE + PLAYER_WIDTH) / 2.0;
+
+/// Things that happened during `Simulation::update` which the scene
+/// might want to react to (play a sound, switch scenes, etc.).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+	/// Index into `Level::entities`.
+	ItemPickedUp(usize),
+	DoorsActivated,
+	DoorEntered(Door),
+}
+
+pub struct Simulation {
+	pub level: Level,
+
+	pub movement_config: MovementConfig,
+	/// `movement_config` with the level's changes.
+	pub movement: MovementModel,
+	/// Surface of every tile, `None` for plain floor.
+	surfaces: Vec<Option<Surface>>,
+
+	pub player_coords: Point2,
+	/// Pixels per second.
+	pub player_velocity: Vector2,
+	pub player_light_radius: f32,
+
+	pub shards_collected: usize,
+	pub are_doors_activated: bool,
+	/// Set once the player walks into an activated door.
+	pub entered_door: Option<Door>,
+	pub doors: Vec<Door>,
+	pub entities_visibility: Vec<bool>,
+}
+
+impl Simulation {
+	pub fn new(level: Level, movement_config: &MovementConfig) -> Self {
+		let player_coords = Point2::new(
+			level.player_x * WALL_SIZE,
+			level.player_y * WALL_SIZE,
+		);
+		let player_light_radius = level.player_light_radius;
+
+		let are_doors_activated = level.shards_for_door_activation == 0;
+
+		let entities_visibility = level.entities.iter()
+			.map(|_| true)
+			.collect();
+
+		let movement = match &level.movement {
+			Some(changes) => movement_config.player.with_override(changes),
+			None => movement_config.player.clone(),
+		};
+		let surfaces = Self::surface_map(&level, movement_config);
+		let doors = level.doors();
+
+		Self {
+			level,
+
+			movement_config: movement_config.clone(),
+			movement,
+			surfaces,
+
+			player_coords,
+			player_velocity: Vector2::zero(),
+			player_light_radius,
+
+			shards_collected: 0,
+			are_doors_activated,
+			entered_door: None,
+			doors,
+			entities_visibility,
+		}
+	}
+
+	/// Advances the simulation by `dt` seconds with the player trying to
+	/// move in `direction` (expected to be either normalized or zero).
+	pub fn update(&mut self, dt: f32, direction: Vector2) -> Vec<Event> {
+		let mut events = Vec::new();
+
+		self.move_player(dt, direction);
+		self.enter_doors(&mut events);
+		self.pick_up_items(&mut events);
+
+		events
+	}
+
+	/// Switches to a changed version of the same level, keeping as much
+	/// progress as still makes sense. Entities are matched by position.
+	/// Returns `false` if the player had to go back to the start.
+	pub fn reload(&mut self, level: Level) -> bool {
+		let mut reloaded = Self::new(level, &self.movement_config);
+
+		for (index, entity) in reloaded.level.entities.iter().enumerate() {
+			let was_picked_up = self.level.entities.iter()
+				.zip(self.entities_visibility.iter())
+				.any(|(old, &visible)| !visible && old.x == entity.x && old.y == entity.y);
+
+			if was_picked_up {
+				reloaded.entities_visibility[index] = false;
+
+				if let PickUpEffect::ActivateDoors = entity.effect {
+					reloaded.shards_collected += 1;
+				}
+			}
+		}
+
+		reloaded.are_doors_activated = reloaded.shards_collected >= reloaded.level.shards_for_door_activation;
+
+		// boosted by a shard
+		if self.player_light_radius != self.level.player_light_radius {
+			reloaded.player_light_radius = self.player_light_radius;
+		}
+
+		let is_player_kept = get_tile_id(&reloaded.level, self.player_coords).is_some()
+			&& !is_blocked(&reloaded.level, self.player_rect(), false);
+
+		if is_player_kept {
+			reloaded.player_coords = self.player_coords;
+			reloaded.player_velocity = self.player_velocity;
+		}
+
+		*self = reloaded;
+
+		is_player_kept
+	}
+
+	fn surface_map(level: &Level, movement_config: &MovementConfig) -> Vec<Option<Surface>> {
+		let mut surfaces = vec![None; level.walls.len()];
+
+		for area in level.surfaces.iter() {
+			// unknown ones are reported by `validation::validate_surfaces`
+			let surface = match movement_config.surfaces.get(&area.surface) {
+				Some(surface) => surface,
+				None => continue,
+			};
+
+			for y in area.y..(area.y + area.height).min(level.height) {
+				for x in area.x..(area.x + area.width).min(level.width) {
+					surfaces[level.width * y + x] = Some(surface.clone());
+				}
+			}
+		}
+
+		surfaces
+	}
+
+	pub fn entity_position(entity: &Entity) -> Point2 {
+		Point2::new(entity.x * WALL_SIZE, entity.y * WALL_SIZE)
+	}
+
+	pub fn player_rect(&self) -> Rect {
+		Rect::new(
+			self.player_coords.x - PLAYER_WIDTH / 2.0,
+			self.player_coords.y - PLAYER_HEIGHT / 2.0,
+			PLAYER_WIDTH,
+			PLAYER_HEIGHT,
+		)
+	}
+
+	pub fn get_tile_id(&self, point: Point2) -> Option<usize> {
+		get_tile_id(&self.level, point)
+	}
+
+	pub fn get_tile_by_point(&self, point: Point2) -> Option<Wall> {
+		get_tile_by_point(&self.level, point)
+	}
+
+	/// Movement model where the player stands right now.
+	pub fn current_movement(&self) -> MovementModel {
+		let surface = self.get_tile_id(self.player_coords)
+			.and_then(|tile_id| self.surfaces[tile_id].as_ref());
+
+		match surface {
+			Some(surface) => self.movement.on(surface),
+			None => self.movement.clone(),
+		}
+	}
+
+	fn move_player(&mut self, dt: f32, direction: Vector2) {
+		let model = self.current_movement();
+		let mut velocity = self.player_velocity;
+
+		if direction.length() > 0.0 {
+			// speed up along the new direction, lose the sideways drift
+			let speed = velocity.dot(direction);
+			let drift = (velocity - direction * speed) * (-model.turn_responsiveness * dt).exp();
+
+			velocity = direction * (speed + model.acceleration * dt).min(model.max_speed) + drift;
+		}
+		else {
+			let speed = velocity.length();
+
+			if speed > 0.0 {
+				velocity = velocity * ((speed - model.friction * dt).max(0.0) / speed);
+			}
+		}
+
+		// surfaces can lower the limit
+		let speed = velocity.length();
+
+		if speed > model.max_speed {
+			velocity = velocity * (model.max_speed / speed);
+		}
+
+		self.player_velocity = velocity;
+
+		if speed > 0.0 {
+			self.move_player_with_collisions(velocity * dt);
+		}
+	}
+
+	/// Doors are triggers, walking into an activated one enters it, once.
+	fn enter_doors(&mut self, events: &mut Vec<Event>) {
+		if !self.are_doors_activated || self.entered_door.is_some() {
+			return;
+		}
+
+		let player_rect = self.player_rect();
+		let door = self.doors.iter()
+			.find(|door| collision::overlaps(player_rect, door.rect(WALL_SIZE)))
+			.cloned();
+
+		if let Some(door) = door {
+			self.entered_door = Some(door);
+			events.push(Event::DoorEntered(door));
+		}
+	}
+
+	fn pick_up_items(&mut self, events: &mut Vec<Event>) {
+		for (index, entity) in self.level.entities.iter().enumerate() {
+			if !self.entities_visibility[index] {
+				continue;
+			}
+
+			let position = Self::entity_position(entity);
+			let distance = util::get_distance(position, self.player_coords);
+
+			if distance < PICK_UP_DISTANCE {
+				match entity.effect {
+					PickUpEffect::IncreasePlayerLightRadius => {
+						self.player_light_radius = PLAYER_LIGHT_RADIUS;
+					},
+					PickUpEffect::ActivateDoors => {
+						self.shards_collected += 1;
+
+						if !self.are_doors_activated && self.shards_collected >= self.level.shards_for_door_activation {
+							self.are_doors_activated = true;
+							events.push(Event::DoorsActivated);
+						}
+					},
+				}
+
+				self.entities_visibility[index] = false;
+				events.push(Event::ItemPickedUp(index));
+			}
+		}
+	}
+
+	fn move_player_with_collisions(&mut self, movement_v: Vector2) {
+		let mut current = self.player_rect();
+		let mut remaining = movement_v;
+		let are_doors_activated = self.are_doors_activated;
+
+		for _ in 0..PLAYER_COLLISION_SLIDES {
+			if remaining.x == 0.0 && remaining.y == 0.0 {
+				break;
+			}
+
+			let hit = collision::sweep(&self.level, WALL_SIZE, current, remaining, |wall| is_solid(wall, are_doors_activated));
+
+			match hit {
+				Some(hit) => {
+					current.x += remaining.x * hit.time + hit.normal.x * collision::EPSILON;
+					current.y += remaining.y * hit.time + hit.normal.y * collision::EPSILON;
+
+					let left = remaining * (1.0 - hit.time);
+
+					if let Some(nudge) = self.corner_nudge(current, hit) {
+						// slip past the corner with what's left of the move, so
+						// it takes a few ticks instead of snapping sideways
+						let budget = left.length();
+						let length = nudge.length();
+						let used = length.min(budget);
+
+						if length > 0.0 {
+							current.x += nudge.x * used / length;
+							current.y += nudge.y * used / length;
+						}
+
+						remaining = if budget > used {
+							left * (1.0 - used / budget)
+						}
+						else {
+							Vector2::zero()
+						};
+					}
+					else {
+						// slide along the wall with what's left
+						remaining = left - hit.normal * left.dot(hit.normal);
+						self.player_velocity = self.player_velocity - hit.normal * self.player_velocity.dot(hit.normal);
+					}
+				},
+				None => {
+					current.x += remaining.x;
+					current.y += remaining.y;
+					break;
+				},
+			}
+		}
+
+		self.player_coords.x = current.x + PLAYER_WIDTH / 2.0;
+		self.player_coords.y = current.y + PLAYER_HEIGHT / 2.0;
+	}
+
+	/// Sideways movement which gets `object` past the tile it hit, if only
+	/// `Level::corner_tolerance` pixels of the tile are in the way and
+	/// there's room to go on behind the corner.
+	fn corner_nudge(&self, object: Rect, hit: collision::Hit) -> Option<Vector2> {
+		let tolerance = self.level.corner_tolerance;
+		let tile_x = hit.tile.0 as f32 * WALL_SIZE;
+		let tile_y = hit.tile.1 as f32 * WALL_SIZE;
+
+		// span of the player and the tile along the hit face
+		let (start, end, tile_start) = if hit.normal.x != 0.0 {
+			(object.y, object.y + object.h, tile_y)
+		}
+		else {
+			(object.x, object.x + object.w, tile_x)
+		};
+
+		let before = end - tile_start;
+		let after = tile_start + WALL_SIZE - start;
+
+		let offset = if before <= tolerance {
+			-(before + collision::EPSILON)
+		}
+		else if after <= tolerance {
+			after + collision::EPSILON
+		}
+		else {
+			return None;
+		};
+
+		let nudge = if hit.normal.x != 0.0 {
+			Vector2::new(0.0, offset)
+		}
+		else {
+			Vector2::new(offset, 0.0)
+		};
+
+		let are_doors_activated = self.are_doors_activated;
+		let is_solid = |wall: &Wall| is_solid(wall, are_doors_activated);
+
+		if collision::sweep(&self.level, WALL_SIZE, object, nudge, is_solid).is_some() {
+			return None;
+		}
+
+		let nudged = Rect::new(object.x + nudge.x, object.y + nudge.y, object.w, object.h);
+		let ahead = -hit.normal * (collision::EPSILON + 1.0);
+
+		if collision::sweep(&self.level, WALL_SIZE, nudged, ahead, is_solid).is_some() {
+			return None;
+		}
+
+		Some(nudge)
+	}
+}
+
+pub fn get_tile_id(level: &Level, point: Point2) -> Option<usize> {
+	if point.x < 0.0 || point.y < 0.0 {
+		return None;
+	}
+
+	let x = (point.x / WALL_SIZE) as usize;
+	let y = (point.y / WALL_SIZE) as usize;
+
+	if x < level.width && y < level.height {
+		Some(level.width * y + x)
+	}
+	else {
+		None
+	}
+}
+
+pub fn get_tile_by_point(level: &Level, point: Point2) -> Option<Wall> {
+	if let Some(tile_id) = get_tile_id(level, point) {
+		Some(level.walls[tile_id].clone())
+	}
+	else {
+		None
+	}
+}
+
+/// Whether the player can't move into `wall`.
+pub fn is_solid(wall: &Wall, are_doors_activated: bool) -> bool {
+	if wall.is_door() {
+		!are_doors_activated
+	}
+	else {
+		wall.is_wall()
+	}
+}
+
+/// Same test as the player's collision, without any side effects.
+/// Doors are solid until they're activated.
+pub fn is_blocked(level: &Level, object: Rect, are_doors_activated: bool) -> bool {
+	collision::overlapping_tiles(level, WALL_SIZE, object)
+		.into_iter()
+		.any(|(x, y)| is_solid(&level.get(x, y), are_doors_activated))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::movement::{MovementOverride, SurfaceArea};
+	use crate::resources::EntityType;
+
+	const DT: f32 = 1.0 / 60.0;
+
+	/// A two tiles tall corridor along rows 1 and 2, walled all around,
+	/// with the player at its left end.
+	fn corridor(length: usize) -> Level {
+		let mut level = Level::new(length, 4, "test");
+
+		for x in 0..length {
+			level.set(x, 0, Wall::S);
+			level.set(x, 3, Wall::S);
+		}
+
+		for y in 1..3 {
+			level.set(0, y, Wall::S);
+			level.set(length - 1, y, Wall::S);
+		}
+
+		level.player_x = 1.5;
+		level.player_y = 2.0;
+
+		level
+	}
+
+	fn shard(x: f32) -> Entity {
+		Entity {
+			entity_type: EntityType::Shard0,
+			x,
+			y: 2.0,
+			light_radius: 0.0,
+			effect: PickUpEffect::ActivateDoors,
+		}
+	}
+
+	fn run(simulation: &mut Simulation, direction: Vector2, ticks: usize) -> Vec<Event> {
+		(0..ticks)
+			.flat_map(|_| simulation.update(DT, direction))
+			.collect()
+	}
+
+	#[test]
+	fn wall_blocks_movement() {
+		let mut simulation = Simulation::new(corridor(6), &MovementConfig::default());
+
+		let events = run(&mut simulation, Vector2::new(1.0, 0.0), 120);
+		let right = simulation.player_rect().x + PLAYER_WIDTH;
+
+		assert!(events.is_empty());
+		assert!(right <= 5.0 * WALL_SIZE);
+		assert!(right > 5.0 * WALL_SIZE - 1.0);
+		assert_eq!(simulation.player_velocity.x, 0.0);
+	}
+
+	#[test]
+	fn item_is_picked_up_once() {
+		let mut level = corridor(10);
+		level.entities.push(shard(4.5));
+		level.shards_for_door_activation = 5;
+
+		let mut simulation = Simulation::new(level, &MovementConfig::default());
+
+		let events = run(&mut simulation, Vector2::new(1.0, 0.0), 60);
+		let pick_ups = events.iter()
+			.filter(|event| **event == Event::ItemPickedUp(0))
+			.count();
+
+		assert_eq!(pick_ups, 1);
+		assert_eq!(simulation.shards_collected, 1);
+		assert!(!simulation.entities_visibility[0]);
+		assert!(!simulation.are_doors_activated);
+	}
+
+	#[test]
+	fn enough_shards_activate_doors() {
+		let mut level = corridor(12);
+		level.entities.push(shard(4.5));
+		level.entities.push(shard(8.5));
+		level.shards_for_door_activation = 2;
+
+		let mut simulation = Simulation::new(level, &MovementConfig::default());
+		let mut activated_with = Vec::new();
+
+		for _ in 0..120 {
+			let events = simulation.update(DT, Vector2::new(1.0, 0.0));
+
+			if events.contains(&Event::DoorsActivated) {
+				activated_with.push(events);
+			}
+		}
+
+		assert_eq!(activated_with.len(), 1);
+		assert!(activated_with[0].contains(&Event::ItemPickedUp(1)));
+		assert!(simulation.are_doors_activated);
+	}
+
+	#[test]
+	fn activated_door_is_entered_once() {
+		let mut level = corridor(6);
+		level.set(5, 1, Wall::B0V);
+		level.set(5, 2, Wall::B1V);
+
+		let mut simulation = Simulation::new(level, &MovementConfig::default());
+
+		let events = run(&mut simulation, Vector2::new(1.0, 0.0), 120);
+		let door = Door {
+			x: 5,
+			y: 1,
+			length: 2,
+			horizontal: false,
+		};
+
+		assert_eq!(events, vec![Event::DoorEntered(door)]);
+		assert_eq!(simulation.entered_door, Some(door));
+	}
+
+	#[test]
+	fn inactive_door_blocks() {
+		let mut level = corridor(6);
+		level.set(5, 1, Wall::B0V);
+		level.set(5, 2, Wall::B1V);
+		level.shards_for_door_activation = 1;
+
+		let mut simulation = Simulation::new(level, &MovementConfig::default());
+
+		let events = run(&mut simulation, Vector2::new(1.0, 0.0), 120);
+
+		assert!(events.is_empty());
+		assert!(simulation.player_rect().x + PLAYER_WIDTH <= 5.0 * WALL_SIZE);
+	}
+
+	/// Open rows 1 and 3-4 joined by a one tile corridor at (2, 2), with
+	/// the player at the bottom, `x` tiles from the left.
+	fn gap(x: f32) -> Level {
+		let mut level = Level::new(5, 6, "test");
+
+		for x in 0..5 {
+			level.set(x, 0, Wall::S);
+			level.set(x, 5, Wall::S);
+
+			if x != 2 {
+				level.set(x, 2, Wall::S);
+			}
+		}
+
+		for y in 0..6 {
+			level.set(0, y, Wall::S);
+			level.set(4, y, Wall::S);
+		}
+
+		level.player_x = x;
+		level.player_y = 4.5;
+
+		level
+	}
+
+	/// Goes up for a second, checking the player never jumps further than
+	/// it could move in a tick. Returns whether it got through the gap.
+	fn go_up(level: Level) -> bool {
+		let mut simulation = Simulation::new(level, &MovementConfig::default());
+		let max_step = simulation.movement.max_speed * DT + 0.05;
+
+		for _ in 0..60 {
+			let before = simulation.player_coords;
+			simulation.update(DT, Vector2::new(0.0, -1.0));
+			let step = (simulation.player_coords - before).length();
+
+			assert!(step <= max_step, "moved {} in a tick", step);
+		}
+
+		simulation.player_rect().y + PLAYER_HEIGHT < 2.0 * WALL_SIZE
+	}
+
+	#[test]
+	fn corridor_is_entered_slightly_off_centre() {
+		for overlap in 1..=6 {
+			let overlap = overlap as f32;
+
+			// past the left corner, then past the right one
+			assert!(go_up(gap((72.0 - overlap) / WALL_SIZE)), "blocked {} px left", overlap);
+			assert!(go_up(gap((88.0 + overlap) / WALL_SIZE)), "blocked {} px right", overlap);
+		}
+	}
+
+	#[test]
+	fn corridor_is_blocked_past_tolerance() {
+		assert!(!go_up(gap((72.0 - 7.0) / WALL_SIZE)));
+		assert!(!go_up(gap((88.0 + 7.0) / WALL_SIZE)));
+
+		let mut level = gap((72.0 - 3.0) / WALL_SIZE);
+		level.corner_tolerance = 2.0;
+		assert!(!go_up(level));
+	}
+
+	#[test]
+	fn flat_wall_does_not_nudge() {
+		// the player is 3 px past the seam of two wall tiles, the nudge
+		// away from the first would run into the second
+		let mut level = gap((96.0 - 3.0 + 8.0) / WALL_SIZE);
+		level.set(2, 2, Wall::S);
+
+		let start = level.player_x * WALL_SIZE;
+		let mut simulation = Simulation::new(level, &MovementConfig::default());
+		run(&mut simulation, Vector2::new(0.0, -1.0), 60);
+
+		assert_eq!(simulation.player_coords.x, start);
+		assert!(simulation.player_rect().y >= 3.0 * WALL_SIZE);
+	}
+
+	#[test]
+	fn nudge_into_side_wall_is_rejected() {
+		// a box wider than the player fits the two tiles wide gap at
+		// (2, 2) and (3, 2) once nudged, unless (3, 3) is in the way
+		let mut level = gap(2.0);
+		level.set(3, 2, Wall::N);
+
+		let object = Rect::new(61.0, 3.0 * WALL_SIZE + collision::EPSILON, 34.0, 16.0);
+		let movement = Vector2::new(0.0, -8.0);
+		let simulation = Simulation::new(level.clone(), &MovementConfig::default());
+		let hit = collision::sweep(&simulation.level, WALL_SIZE, object, movement, |wall| is_solid(wall, false)).unwrap();
+
+		assert_eq!(hit.tile, (1, 2));
+		assert!(simulation.corner_nudge(object, hit).is_some());
+
+		level.set(3, 3, Wall::S);
+		let simulation = Simulation::new(level, &MovementConfig::default());
+
+		assert_eq!(simulation.corner_nudge(object, hit), None);
+	}
+
+	fn ice_config() -> MovementConfig {
+		let mut config = MovementConfig::default();
+		config.surfaces.insert("ice".to_string(), Surface {
+			max_speed: 0.5,
+			friction: 0.05,
+			..Surface::default()
+		});
+
+		config
+	}
+
+	fn ice(x: usize, width: usize) -> SurfaceArea {
+		SurfaceArea {
+			surface: "ice".to_string(),
+			x,
+			y: 1,
+			width,
+			height: 2,
+		}
+	}
+
+	#[test]
+	fn surface_under_the_player_changes_the_movement() {
+		let mut level = corridor(10);
+		level.surfaces.push(ice(4, 2));
+
+		let config = ice_config();
+		let mut simulation = Simulation::new(level, &config);
+
+		assert_eq!(simulation.current_movement(), config.player);
+
+		for &x in [4.0, 5.9].iter() {
+			simulation.player_coords = Point2::new(x * WALL_SIZE, 2.0 * WALL_SIZE);
+
+			assert_eq!(simulation.current_movement(), config.player.on(&config.surfaces["ice"]));
+		}
+
+		simulation.player_coords = Point2::new(6.5 * WALL_SIZE, 2.0 * WALL_SIZE);
+
+		assert_eq!(simulation.current_movement(), config.player);
+	}
+
+	#[test]
+	fn unknown_and_oversized_surfaces() {
+		let mut level = corridor(10);
+		level.surfaces.push(SurfaceArea {
+			surface: "lava".to_string(),
+			..ice(1, 1)
+		});
+		level.surfaces.push(ice(8, 5));
+
+		let config = ice_config();
+		let mut simulation = Simulation::new(level, &config);
+
+		assert_eq!(simulation.current_movement(), config.player);
+
+		simulation.player_coords = Point2::new(9.5 * WALL_SIZE, 2.0 * WALL_SIZE);
+
+		assert_eq!(simulation.current_movement(), config.player.on(&config.surfaces["ice"]));
+	}
+
+	#[test]
+	fn surface_scales_the_level_override() {
+		let mut level = corridor(10);
+		level.movement = Some(MovementOverride {
+			max_speed: Some(100.0),
+			..MovementOverride::default()
+		});
+		level.surfaces.push(ice(1, 1));
+
+		let simulation = Simulation::new(level, &ice_config());
+		let model = simulation.current_movement();
+
+		assert_eq!(simulation.movement.max_speed, 100.0);
+		assert_eq!(model.max_speed, 50.0);
+		assert_eq!(model.friction, 300.0);
+	}
+
+	#[test]
+	fn speed_changes_every_tick() {
+		let mut simulation = Simulation::new(corridor(40), &MovementConfig::default());
+		let model = simulation.current_movement();
+
+		let mut speeds = Vec::new();
+
+		for _ in 0..6 {
+			simulation.update(DT, Vector2::new(1.0, 0.0));
+			speeds.push(simulation.player_velocity.x);
+		}
+
+		for _ in 0..4 {
+			simulation.update(DT, Vector2::zero());
+			speeds.push(simulation.player_velocity.x);
+		}
+
+		let step = model.acceleration * DT;
+		let expected = [step, 2.0 * step, 3.0 * step, 4.0 * step, 300.0, 300.0, 200.0, 100.0, 0.0, 0.0];
+
+		assert!((model.friction * DT - 100.0).abs() < 0.01);
+
+		for (speed, expected) in speeds.iter().zip(expected.iter()) {
+			assert!((speed - expected).abs() < 0.01, "{:?}", speeds);
+		}
+	}
+}
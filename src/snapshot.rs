@@ -0,0 +1,60 @@
+//! Exact state of a level being played, for quicksaves and for
+//! reproducing bugs. Stored as JSON since seeds and hashes don't fit
+//! into TOML integers.
+use crate::lighting::ExploredTiles;
+use ggez;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path;
+
+const QUICKSAVE_FILE: &str = "quicksave.json";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Snapshot {
+	/// `Level::content_hash` of the level it was taken in.
+	pub level_hash: u64,
+	/// `Level::seed`, tells generated levels apart.
+	pub level_seed: Option<u64>,
+	/// `LevelConfiguration::seed`, so the walls look the same.
+	pub tile_seed: u64,
+	pub time: f32,
+
+	pub player_x: f32,
+	pub player_y: f32,
+	pub player_velocity_x: f32,
+	pub player_velocity_y: f32,
+	pub player_light_radius: f32,
+
+	pub shards_collected: usize,
+	pub are_doors_activated: bool,
+	pub entities_visibility: Vec<bool>,
+	pub entities_seen: Vec<bool>,
+	pub explored: ExploredTiles,
+}
+
+impl Snapshot {
+	pub fn quicksave_path(context: &ggez::Context) -> path::PathBuf {
+		ggez::filesystem::user_data_dir(context).join(QUICKSAVE_FILE)
+	}
+
+	pub fn load(path: &path::Path) -> Result<Self, String> {
+		let content = fs::read_to_string(path)
+			.map_err(|e| e.to_string())?;
+
+		serde_json::from_str(&content)
+			.map_err(|e| e.to_string())
+	}
+
+	pub fn write(&self, path: &path::Path) -> Result<(), String> {
+		let content = serde_json::to_string_pretty(self)
+			.map_err(|e| e.to_string())?;
+
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)
+				.map_err(|e| e.to_string())?;
+		}
+
+		fs::write(path, content)
+			.map_err(|e| e.to_string())
+	}
+}
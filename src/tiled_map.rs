@@ -0,0 +1,540 @@
+//! Levels made in Tiled, saved as JSON (`.json`/`.tmj`) or TMX.
+//!
+//! The first tile layer holds the walls. Tile ids are local to the first
+//! tileset: 0 is a solid wall, 1-4 are the 2-tile doors (`B0H`, `B1H`,
+//! `B0V`, `B1V`), 5-10 the 3-tile ones (`D0H` ... `D2V`) and an empty
+//! cell is an empty tile. Flipped tiles are treated as unflipped.
+//!
+//! Objects with the type (or class) `player` set the player start, ones
+//! with `shard` become entities using their `effect`, `light_radius` and
+//! `entity_type` properties. The map's own properties fill in `key`,
+//! `player_light_radius` and the other scalar level fields.
+use crate::resources::{Entity, EntityType, Level, PickUpEffect, Wall};
+use serde_json::Value;
+use std::collections::HashMap;
+use xml::reader::{EventReader, XmlEvent};
+
+const WALL_TILES: [Wall; 11] = [
+	Wall::S,
+	Wall::B0H,
+	Wall::B1H,
+	Wall::B0V,
+	Wall::B1V,
+	Wall::D0H,
+	Wall::D1H,
+	Wall::D2H,
+	Wall::D0V,
+	Wall::D1V,
+	Wall::D2V,
+];
+
+/// Tiled keeps flipping in the highest bits of a tile id.
+const FLIP_FLAGS: u32 = 0xE000_0000;
+
+#[derive(Debug, Default)]
+struct TiledObject {
+	kind: String,
+	x: f32,
+	y: f32,
+	width: f32,
+	height: f32,
+	/// Tile objects are anchored at their bottom left corner.
+	is_tile: bool,
+	properties: HashMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+struct TiledMap {
+	width: usize,
+	height: usize,
+	tile_width: f32,
+	tile_height: f32,
+	first_gid: Option<u32>,
+	tiles: Option<Vec<u32>>,
+	objects: Vec<TiledObject>,
+	properties: HashMap<String, String>,
+}
+
+pub fn parse_json(content: &str) -> Result<Level, String> {
+	let json: Value = serde_json::from_str(content)
+		.map_err(|e| e.to_string())?;
+
+	if json["infinite"].as_bool() == Some(true) {
+		return Err("Infinite Tiled maps aren't supported!".to_owned());
+	}
+
+	let mut map = TiledMap {
+		width: json_usize(&json, "width")?,
+		height: json_usize(&json, "height")?,
+		tile_width: json_f32(&json, "tilewidth")?,
+		tile_height: json_f32(&json, "tileheight")?,
+		first_gid: json["tilesets"][0]["firstgid"].as_u64().map(|gid| gid as u32),
+		properties: json_properties(&json["properties"]),
+		..TiledMap::default()
+	};
+
+	for layer in json["layers"].as_array().into_iter().flatten() {
+		match layer["type"].as_str() {
+			Some("tilelayer") if map.tiles.is_none() => {
+				if layer["encoding"].as_str().map_or(false, |encoding| encoding != "csv") {
+					return Err("Only CSV encoded tile layers are supported!".to_owned());
+				}
+
+				let tiles = layer["data"].as_array()
+					.ok_or("Tile layer has no data!")?
+					.iter()
+					.map(|gid| gid.as_u64().map(|gid| gid as u32).ok_or("Invalid tile id!"))
+					.collect::<Result<Vec<_>, _>>()?;
+
+				map.tiles = Some(tiles);
+			},
+			Some("objectgroup") => {
+				for object in layer["objects"].as_array().into_iter().flatten() {
+					// "type" was renamed to "class" in Tiled 1.9
+					let kind = object["type"].as_str()
+						.filter(|kind| !kind.is_empty())
+						.or(object["class"].as_str())
+						.unwrap_or("");
+
+					map.objects.push(TiledObject {
+						kind: kind.to_owned(),
+						x: object["x"].as_f64().unwrap_or(0.0) as f32,
+						y: object["y"].as_f64().unwrap_or(0.0) as f32,
+						width: object["width"].as_f64().unwrap_or(0.0) as f32,
+						height: object["height"].as_f64().unwrap_or(0.0) as f32,
+						is_tile: object["gid"].is_u64(),
+						properties: json_properties(&object["properties"]),
+					});
+				}
+			},
+			_ => {},
+		}
+	}
+
+	map.into_level()
+}
+
+pub fn parse_tmx(content: &str) -> Result<Level, String> {
+	let mut map = TiledMap::default();
+
+	// names of all the currently open elements
+	let mut path: Vec<String> = Vec::new();
+	let mut data_encoding = None;
+	let mut data_text = String::new();
+	let mut data_tiles: Vec<u32> = Vec::new();
+	let mut object: Option<TiledObject> = None;
+
+	for event in EventReader::from_str(content) {
+		match event.map_err(|e| e.to_string())? {
+			XmlEvent::StartElement { name, attributes, .. } => {
+				let attribute = |key: &str| {
+					attributes.iter()
+						.find(|attribute| attribute.name.local_name == key)
+						.map(|attribute| attribute.value.as_str())
+				};
+				let parent = path.last().map(|parent| parent.as_str());
+
+				match (parent, name.local_name.as_str()) {
+					(None, "map") => {
+						if attribute("infinite") == Some("1") {
+							return Err("Infinite Tiled maps aren't supported!".to_owned());
+						}
+
+						map.width = parse_attribute(attribute("width"), "width")?;
+						map.height = parse_attribute(attribute("height"), "height")?;
+						map.tile_width = parse_attribute(attribute("tilewidth"), "tilewidth")?;
+						map.tile_height = parse_attribute(attribute("tileheight"), "tileheight")?;
+					},
+					(Some("map"), "tileset") if map.first_gid.is_none() => {
+						map.first_gid = Some(parse_attribute(attribute("firstgid"), "firstgid")?);
+					},
+					(Some("layer"), "data") if map.tiles.is_none() => {
+						data_encoding = Some(attribute("encoding").unwrap_or("xml").to_owned());
+						data_text.clear();
+						data_tiles.clear();
+
+						if let Some(compression) = attribute("compression") {
+							return Err(format!("Compressed ({}) tile layers aren't supported!", compression));
+						}
+					},
+					(Some("data"), "tile") if data_encoding.is_some() => {
+						data_tiles.push(attribute("gid").map_or(Ok(0), |gid| gid.parse().map_err(|_| "Invalid tile id!"))?);
+					},
+					(Some("objectgroup"), "object") => {
+						object = Some(TiledObject {
+							kind: attribute("type").or(attribute("class")).unwrap_or("").to_owned(),
+							x: parse_attribute(attribute("x").or(Some("0")), "x")?,
+							y: parse_attribute(attribute("y").or(Some("0")), "y")?,
+							width: parse_attribute(attribute("width").or(Some("0")), "width")?,
+							height: parse_attribute(attribute("height").or(Some("0")), "height")?,
+							is_tile: attribute("gid").is_some(),
+							properties: HashMap::new(),
+						});
+					},
+					(Some("properties"), "property") => {
+						let owner = path.iter().rev().nth(1).map(|owner| owner.as_str());
+
+						if let (Some(key), Some(value)) = (attribute("name"), attribute("value")) {
+							match owner {
+								Some("map") => {
+									map.properties.insert(key.to_owned(), value.to_owned());
+								},
+								Some("object") => {
+									if let Some(object) = object.as_mut() {
+										object.properties.insert(key.to_owned(), value.to_owned());
+									}
+								},
+								_ => {},
+							}
+						}
+					},
+					_ => {},
+				}
+
+				path.push(name.local_name);
+			},
+			XmlEvent::Characters(text) => {
+				if path.last().map(|name| name.as_str()) == Some("data") && data_encoding.is_some() {
+					data_text.push_str(&text);
+				}
+			},
+			XmlEvent::EndElement { name } => {
+				path.pop();
+
+				match name.local_name.as_str() {
+					"data" => {
+						match data_encoding.take().as_ref().map(|encoding| encoding.as_str()) {
+							Some("csv") => {
+								let tiles = data_text.split(',')
+									.map(|gid| gid.trim().parse().map_err(|_| format!("Invalid tile id {:?}!", gid.trim())))
+									.collect::<Result<Vec<_>, _>>()?;
+
+								map.tiles = Some(tiles);
+							},
+							Some("xml") => map.tiles = Some(data_tiles.clone()),
+							Some(encoding) => return Err(format!("Tile layers encoded with {} aren't supported, use CSV!", encoding)),
+							None => {},
+						}
+					},
+					"object" => {
+						if let Some(object) = object.take() {
+							map.objects.push(object);
+						}
+					},
+					_ => {},
+				}
+			},
+			_ => {},
+		}
+	}
+
+	map.into_level()
+}
+
+impl TiledMap {
+	fn into_level(self) -> Result<Level, String> {
+		let key = self.properties.get("key")
+			.ok_or("Map has no \"key\" property with the wall tileset!")?;
+
+		let tiles = self.tiles.as_ref()
+			.ok_or("Map has no tile layer!")?;
+
+		if tiles.len() != self.width * self.height {
+			return Err(format!("Tile layer has {} tiles, but the map is {}x{}!", tiles.len(), self.width, self.height));
+		}
+
+		let first_gid = self.first_gid.unwrap_or(1);
+		let mut level = Level::new(self.width, self.height, key);
+
+		for (tile_id, &gid) in tiles.iter().enumerate() {
+			let gid = gid & !FLIP_FLAGS;
+
+			if gid == 0 {
+				continue;
+			}
+
+			let wall = gid.checked_sub(first_gid)
+				.and_then(|local_id| WALL_TILES.get(local_id as usize))
+				.ok_or_else(|| format!(
+					"Unknown tile id {} at {}, {}!", gid, tile_id % self.width, tile_id / self.width
+				))?;
+
+			level.walls[tile_id] = wall.clone();
+		}
+
+		let mut door_shards = 0;
+
+		for object in self.objects.iter() {
+			// in tiles, objects are centered on their rect
+			let top = if object.is_tile { object.y - object.height } else { object.y };
+			let x = (object.x + object.width / 2.0) / self.tile_width;
+			let y = (top + object.height / 2.0) / self.tile_height;
+
+			match object.kind.as_str() {
+				"player" => {
+					level.player_x = x;
+					level.player_y = y;
+				},
+				"shard" => {
+					let effect = match object.properties.get("effect").map(|effect| effect.as_str()) {
+						Some("ActivateDoors") | None => PickUpEffect::ActivateDoors,
+						Some("IncreasePlayerLightRadius") => PickUpEffect::IncreasePlayerLightRadius,
+						Some(effect) => return Err(format!("Unknown shard effect {}!", effect)),
+					};
+
+					let entity_type = match object.properties.get("entity_type").map(|entity_type| entity_type.as_str()) {
+						Some("Shard0") | None => EntityType::Shard0,
+						Some("Shard1") => EntityType::Shard1,
+						Some("Shard2") => EntityType::Shard2,
+						Some("Shard3") => EntityType::Shard3,
+						Some("Shard4") => EntityType::Shard4,
+						Some(entity_type) => return Err(format!("Unknown shard entity type {}!", entity_type)),
+					};
+
+					if let PickUpEffect::ActivateDoors = effect {
+						door_shards += 1;
+					}
+
+					level.entities.push(Entity {
+						entity_type,
+						x,
+						y,
+						light_radius: parse_property(&object.properties, "light_radius")?.unwrap_or(0.0),
+						effect,
+					});
+				},
+				_ => {},
+			}
+		}
+
+		level.player_light_radius = parse_property(&self.properties, "player_light_radius")?.unwrap_or(0.0);
+		level.shards_for_door_activation = parse_property(&self.properties, "shards_for_door_activation")?.unwrap_or(door_shards);
+		level.remember_walls = parse_property(&self.properties, "remember_walls")?.unwrap_or(false);
+		level.hide_minimap = parse_property(&self.properties, "hide_minimap")?.unwrap_or(false);
+		level.corner_tolerance = parse_property(&self.properties, "corner_tolerance")?.unwrap_or(level.corner_tolerance);
+		level.seed = parse_property(&self.properties, "seed")?;
+
+		Ok(level)
+	}
+}
+
+fn json_usize(json: &Value, key: &str) -> Result<usize, String> {
+	json[key].as_u64()
+		.map(|value| value as usize)
+		.ok_or_else(|| format!("Map has no {}!", key))
+}
+
+fn json_f32(json: &Value, key: &str) -> Result<f32, String> {
+	json[key].as_f64()
+		.map(|value| value as f32)
+		.ok_or_else(|| format!("Map has no {}!", key))
+}
+
+/// Tiled JSON properties are typed, keep them as strings like in TMX.
+fn json_properties(properties: &Value) -> HashMap<String, String> {
+	properties.as_array()
+		.into_iter()
+		.flatten()
+		.filter_map(|property| {
+			let name = property["name"].as_str()?;
+			let value = match &property["value"] {
+				Value::String(value) => value.clone(),
+				Value::Null => return None,
+				value => value.to_string(),
+			};
+
+			Some((name.to_owned(), value))
+		})
+		.collect()
+}
+
+fn parse_attribute<T: std::str::FromStr>(value: Option<&str>, name: &str) -> Result<T, String> {
+	value
+		.ok_or_else(|| format!("Missing attribute {}!", name))?
+		.parse()
+		.map_err(|_| format!("Invalid attribute {}!", name))
+}
+
+fn parse_property<T: std::str::FromStr>(properties: &HashMap<String, String>, name: &str) -> Result<Option<T>, String> {
+	match properties.get(name) {
+		Some(value) => value.parse()
+			.map(Some)
+			.map_err(|_| format!("Invalid property {} = {:?}!", name, value)),
+		None => Ok(None),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.9" orientation="orthogonal" width="4" height="3" tilewidth="32" tileheight="32" infinite="0">
+ <properties>
+  <property name="key" value="test"/>
+  <property name="player_light_radius" type="float" value="64"/>
+ </properties>
+ <tileset firstgid="1" source="walls.tsx"/>
+ <layer id="1" name="walls" width="4" height="3">
+  <data encoding="csv">
+1,2,3,1,
+1,0,0,4,
+1,1,1,5
+</data>
+ </layer>
+ <objectgroup id="2" name="entities">
+  <object id="1" type="player" x="32" y="32" width="32" height="32"/>
+  <object id="2" class="shard" gid="12" x="64" y="64" width="32" height="32">
+   <properties>
+    <property name="effect" value="IncreasePlayerLightRadius"/>
+    <property name="light_radius" type="float" value="48"/>
+   </properties>
+  </object>
+ </objectgroup>
+</map>
+"#;
+
+	const JSON: &str = r#"{
+		"width": 2, "height": 1, "tilewidth": 32, "tileheight": 32, "infinite": false,
+		"properties": [{ "name": "key", "type": "string", "value": "test" }],
+		"tilesets": [{ "firstgid": 10, "source": "walls.tsx" }],
+		"layers": [
+			{ "type": "tilelayer", "data": [10, 0] },
+			{ "type": "objectgroup", "objects": [{ "class": "shard", "x": 32, "y": 0, "width": 32, "height": 32 }] }
+		]
+	}"#;
+
+	fn tmx_error(from: &str, to: &str) -> String {
+		assert!(TMX.contains(from));
+
+		parse_tmx(&TMX.replace(from, to)).unwrap_err()
+	}
+
+	#[test]
+	fn tmx_map() {
+		let level = parse_tmx(TMX).unwrap();
+
+		assert_eq!(level.key, "test");
+		assert_eq!((level.width, level.height), (4, 3));
+		assert_eq!(level.walls, vec![
+			Wall::S, Wall::B0H, Wall::B1H, Wall::S,
+			Wall::S, Wall::N, Wall::N, Wall::B0V,
+			Wall::S, Wall::S, Wall::S, Wall::B1V,
+		]);
+		assert_eq!((level.player_x, level.player_y), (1.5, 1.5));
+		assert_eq!(level.player_light_radius, 64.0);
+
+		// the shard is a tile object, so its y is its bottom edge
+		assert_eq!(level.entities.len(), 1);
+		assert_eq!((level.entities[0].x, level.entities[0].y), (2.5, 1.5));
+		assert_eq!(level.entities[0].effect, PickUpEffect::IncreasePlayerLightRadius);
+		assert_eq!(level.entities[0].light_radius, 48.0);
+		assert_eq!(level.shards_for_door_activation, 0);
+	}
+
+	#[test]
+	fn tmx_xml_tile_data() {
+		let data = "<data>\n<tile gid=\"1\"/><tile/><tile gid=\"2147483650\"/><tile gid=\"1\"/>\n";
+		let level = parse_tmx(&TMX
+			.replace("width=\"4\" height=\"3\" tilewidth", "width=\"4\" height=\"1\" tilewidth")
+			.replace("<data encoding=\"csv\">\n1,2,3,1,\n1,0,0,4,\n1,1,1,5\n", data)
+		).unwrap();
+
+		// the third tile is flipped horizontally
+		assert_eq!(level.walls, vec![Wall::S, Wall::N, Wall::B0H, Wall::S]);
+	}
+
+	#[test]
+	fn json_map() {
+		let level = parse_json(JSON).unwrap();
+
+		assert_eq!(level.key, "test");
+		assert_eq!(level.walls, vec![Wall::S, Wall::N]);
+		assert_eq!((level.entities[0].x, level.entities[0].y), (1.5, 0.5));
+		assert_eq!(level.entities[0].effect, PickUpEffect::ActivateDoors);
+		assert_eq!(level.shards_for_door_activation, 1);
+	}
+
+	#[test]
+	fn json_errors() {
+		assert_eq!(parse_json(&JSON.replace("\"infinite\": false", "\"infinite\": true")).unwrap_err(), "Infinite Tiled maps aren't supported!");
+		assert_eq!(parse_json(&JSON.replace("\"width\": 2, ", "")).unwrap_err(), "Map has no width!");
+		assert_eq!(parse_json(&JSON.replace("\"data\"", "\"encoding\": \"base64\", \"data\"")).unwrap_err(), "Only CSV encoded tile layers are supported!");
+		assert_eq!(parse_json(&JSON.replace("\"data\"", "\"chunks\"")).unwrap_err(), "Tile layer has no data!");
+		assert_eq!(parse_json(&JSON.replace("[10, 0]", "[10, -1]")).unwrap_err(), "Invalid tile id!");
+		assert!(parse_json("{").is_err());
+	}
+
+	#[test]
+	fn infinite_map() {
+		assert_eq!(tmx_error("infinite=\"0\"", "infinite=\"1\""), "Infinite Tiled maps aren't supported!");
+	}
+
+	#[test]
+	fn missing_attribute() {
+		assert_eq!(tmx_error("width=\"4\" height=\"3\" tilewidth", "height=\"3\" tilewidth"), "Missing attribute width!");
+		assert_eq!(tmx_error("tilewidth=\"32\"", "tilewidth=\"wide\""), "Invalid attribute tilewidth!");
+	}
+
+	#[test]
+	fn compressed_tiles() {
+		assert_eq!(tmx_error("encoding=\"csv\"", "encoding=\"base64\" compression=\"zlib\""), "Compressed (zlib) tile layers aren't supported!");
+	}
+
+	#[test]
+	fn unsupported_encoding() {
+		assert_eq!(tmx_error("encoding=\"csv\"", "encoding=\"base64\""), "Tile layers encoded with base64 aren't supported, use CSV!");
+	}
+
+	#[test]
+	fn invalid_tile_id() {
+		assert_eq!(tmx_error("1,1,1,5", "1,1,x,5"), "Invalid tile id \"x\"!");
+	}
+
+	#[test]
+	fn missing_key() {
+		assert_eq!(tmx_error("name=\"key\"", "name=\"tileset\""), "Map has no \"key\" property with the wall tileset!");
+	}
+
+	#[test]
+	fn missing_tile_layer() {
+		let content = TMX
+			.replace("<layer id=\"1\" name=\"walls\" width=\"4\" height=\"3\">", "<group>")
+			.replace("</layer>", "</group>");
+
+		assert_eq!(parse_tmx(&content).unwrap_err(), "Map has no tile layer!");
+	}
+
+	#[test]
+	fn tile_count_mismatch() {
+		assert_eq!(tmx_error("1,1,1,5", "1,1,1"), "Tile layer has 11 tiles, but the map is 4x3!");
+	}
+
+	#[test]
+	fn unknown_tile_id() {
+		assert_eq!(tmx_error("1,1,1,5", "1,1,1,13"), "Unknown tile id 13 at 3, 2!");
+	}
+
+	#[test]
+	fn unknown_shard_effect() {
+		assert_eq!(tmx_error("IncreasePlayerLightRadius", "Teleport"), "Unknown shard effect Teleport!");
+	}
+
+	#[test]
+	fn unknown_entity_type() {
+		assert_eq!(
+			tmx_error("name=\"light_radius\" type=\"float\" value=\"48\"", "name=\"entity_type\" value=\"Shard9\""),
+			"Unknown shard entity type Shard9!"
+		);
+	}
+
+	#[test]
+	fn invalid_property() {
+		assert_eq!(tmx_error("value=\"64\"", "value=\"bright\""), "Invalid property player_light_radius = \"bright\"!");
+	}
+
+	#[test]
+	fn malformed_xml() {
+		assert!(parse_tmx(&TMX.replace("</map>", "")).is_err());
+	}
+}
@@ -1,5 +1,4 @@
 use crate::types::{Point2, Rect, Vector2};
-use ggez;
 
 /// A couple handy re-exports from Euclid
 // pub use euclid::point2;
@@ -0,0 +1,552 @@
+//! Sanity checks for level files.
+//!
+//! `validate` never fails by itself, it just lists everything that looks
+//! wrong, so it can be used both while loading a level (as warnings) and
+//! from the `--validate-levels` command line mode.
+use crate::movement::MovementConfig;
+use crate::pathfinding;
+use crate::resources::{self, DoorPart, Level, PickUpEffect, TransitionList, TransitionType, Wall};
+use crate::simulation::{PLAYER_HEIGHT, PLAYER_WIDTH};
+use std::fmt;
+use std::fs;
+use std::path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Diagnostic {
+	PlayerOutsideGrid { x: f32, y: f32 },
+	PlayerInsideWall { x: f32, y: f32 },
+	EntityOutsideGrid { index: usize, x: f32, y: f32 },
+	EntityInsideWall { index: usize, x: f32, y: f32 },
+	NotEnoughShards { required: usize, available: usize },
+	NotEnoughReachableShards { required: usize, reachable: usize },
+	MalformedDoor { x: usize, y: usize, wall: Wall },
+	NoDoor,
+	UnreachableDoor,
+	UnreachableEntity { index: usize, x: f32, y: f32 },
+	InvalidTileVariant { x: usize, y: usize },
+	InvalidCornerTolerance { tolerance: f32 },
+	UnknownSurface { x: usize, y: usize, surface: String },
+	ExitWithoutDoor { x: usize, y: usize },
+	UnknownExitTransition { x: usize, y: usize, transition: String },
+	UnknownTileset { key: String },
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match self {
+			Self::PlayerOutsideGrid { x, y } =>
+				write!(f, "player starts outside of the level at {}, {}", x, y),
+			Self::PlayerInsideWall { x, y } =>
+				write!(f, "player starts inside a wall at {}, {}", x, y),
+			Self::EntityOutsideGrid { index, x, y } =>
+				write!(f, "entity #{} is outside of the level at {}, {}", index, x, y),
+			Self::EntityInsideWall { index, x, y } =>
+				write!(f, "entity #{} is inside a wall at {}, {}", index, x, y),
+			Self::NotEnoughShards { required, available } =>
+				write!(f, "doors need {} shards, but there are only {}", required, available),
+			Self::NotEnoughReachableShards { required, reachable } =>
+				write!(f, "doors need {} shards, but only {} can be reached", required, reachable),
+			Self::MalformedDoor { x, y, wall } =>
+				write!(f, "door tile {:?} at {}, {} isn't part of a complete door", wall, x, y),
+			Self::NoDoor =>
+				write!(f, "level has no doors"),
+			Self::UnreachableDoor =>
+				write!(f, "no door can be reached from the player's start"),
+			Self::UnreachableEntity { index, x, y } =>
+				write!(f, "entity #{} at {}, {} can't be reached from the player's start", index, x, y),
+			Self::InvalidTileVariant { x, y } =>
+				write!(f, "tile variant at {}, {} is outside of the level or out of range", x, y),
+			Self::InvalidCornerTolerance { tolerance } =>
+				write!(f, "corner tolerance {} is negative or larger than the player", tolerance),
+			Self::UnknownSurface { x, y, surface } =>
+				write!(f, "surface at {}, {} is unknown {}", x, y, surface),
+			Self::ExitWithoutDoor { x, y } =>
+				write!(f, "exit at {}, {} isn't on a door", x, y),
+			Self::UnknownExitTransition { x, y, transition } =>
+				write!(f, "exit at {}, {} leads to unknown transition {}", x, y, transition),
+			Self::UnknownTileset { key } =>
+				write!(f, "wall tileset {} doesn't exist", key),
+		}
+	}
+}
+
+/// Expects `walls` to match the level's size, parsing already rejects
+/// levels where it doesn't.
+pub fn validate(level: &Level) -> Vec<Diagnostic> {
+	let mut diagnostics = Vec::new();
+
+	match get_wall(level, level.player_x, level.player_y) {
+		None => diagnostics.push(Diagnostic::PlayerOutsideGrid { x: level.player_x, y: level.player_y }),
+		Some(wall) if !wall.is_empty() => diagnostics.push(Diagnostic::PlayerInsideWall { x: level.player_x, y: level.player_y }),
+		_ => {},
+	}
+
+	for (index, entity) in level.entities.iter().enumerate() {
+		match get_wall(level, entity.x, entity.y) {
+			None => diagnostics.push(Diagnostic::EntityOutsideGrid { index, x: entity.x, y: entity.y }),
+			Some(wall) if !wall.is_empty() => diagnostics.push(Diagnostic::EntityInsideWall { index, x: entity.x, y: entity.y }),
+			_ => {},
+		}
+	}
+
+	let available = level.entities.iter()
+		.filter(|entity| match entity.effect {
+			PickUpEffect::ActivateDoors => true,
+			_ => false,
+		})
+		.count();
+
+	if level.shards_for_door_activation > available {
+		diagnostics.push(Diagnostic::NotEnoughShards {
+			required: level.shards_for_door_activation,
+			available,
+		});
+	}
+
+	for y in 0..level.height {
+		for x in 0..level.width {
+			let wall = level.get(x, y);
+
+			if let Some(part) = wall.door_part() {
+				if !is_complete_door(level, x, y, part) {
+					diagnostics.push(Diagnostic::MalformedDoor { x, y, wall });
+				}
+			}
+		}
+	}
+
+	for variant in level.tile_variants.iter() {
+		let is_inside = variant.x < level.width && variant.y < level.height;
+		let is_side_valid = variant.side.map_or(true, |side| side < resources::TILE_COUNT);
+		let is_corner_valid = variant.corner.map_or(true, |corner| corner < resources::CORNER_COUNT);
+
+		if !is_inside || !is_side_valid || !is_corner_valid {
+			diagnostics.push(Diagnostic::InvalidTileVariant { x: variant.x, y: variant.y });
+		}
+	}
+
+	if level.corner_tolerance < 0.0 || level.corner_tolerance > PLAYER_WIDTH.min(PLAYER_HEIGHT) {
+		diagnostics.push(Diagnostic::InvalidCornerTolerance { tolerance: level.corner_tolerance });
+	}
+
+	let doors = level.doors();
+
+	for exit in level.exits.iter() {
+		if !doors.iter().any(|door| door.contains(exit.x, exit.y)) {
+			diagnostics.push(Diagnostic::ExitWithoutDoor { x: exit.x, y: exit.y });
+		}
+	}
+
+	// a player stuck in a wall can't reach anything, no need to repeat that
+	let is_player_placed = !diagnostics.iter().any(|diagnostic| match diagnostic {
+		Diagnostic::PlayerOutsideGrid { .. } | Diagnostic::PlayerInsideWall { .. } => true,
+		_ => false,
+	});
+
+	if is_player_placed {
+		validate_reachability(level, &mut diagnostics);
+	}
+
+	diagnostics
+}
+
+/// Surfaces have to be defined in the movement config, which levels
+/// don't know about, so they are checked on their own.
+pub fn validate_surfaces(level: &Level, movement: &MovementConfig) -> Vec<Diagnostic> {
+	level.surfaces.iter()
+		.filter(|area| !movement.surfaces.contains_key(&area.surface))
+		.map(|area| Diagnostic::UnknownSurface {
+			x: area.x,
+			y: area.y,
+			surface: area.surface.clone(),
+		})
+		.collect()
+}
+
+/// Loads every level referenced by `transitions.toml` straight from the
+/// resource directory and prints what's wrong with them.
+/// Returns `false` if any problems were found.
+pub fn validate_levels(resource_path: &path::Path) -> bool {
+	let transitions_path = resource_path.join("transitions.toml");
+
+	let transition_list = match fs::read_to_string(&transitions_path)
+		.map_err(|e| e.to_string())
+		.and_then(|content| TransitionList::parse(&content).map_err(|e| e.to_string()))
+	{
+		Ok(list) => list,
+		Err(e) => {
+			println!("{:?}: {}", transitions_path, e);
+			return false;
+		},
+	};
+
+	let movement_path = resource_path.join("movement.toml");
+
+	let movement = if movement_path.is_file() {
+		match fs::read_to_string(&movement_path)
+			.map_err(|e| e.to_string())
+			.and_then(|content| toml::from_str(&content).map_err(|e| e.to_string()))
+		{
+			Ok(movement) => movement,
+			Err(e) => {
+				println!("{:?}: {}", movement_path, e);
+				return false;
+			},
+		}
+	}
+	else {
+		MovementConfig::default()
+	};
+
+	let mut is_valid = true;
+
+	for transition in transition_list.transitions.iter() {
+		match transition.transition_type {
+			TransitionType::ToLevel => {},
+			_ => continue,
+		}
+
+		let levels_path = resource_path.join("levels");
+		let level_path = resources::LEVEL_EXTENSIONS.iter()
+			.map(|extension| levels_path.join(format!("{}.{}", transition.name, extension)))
+			.find(|path| path.is_file())
+			.unwrap_or_else(|| levels_path.join(format!("{}.toml", transition.name)));
+
+		let level = match fs::read_to_string(&level_path)
+			.map_err(|e| e.to_string())
+			.and_then(|content| Level::parse_file(&level_path, &content).map_err(|e| e.to_string()))
+		{
+			Ok(level) => level,
+			Err(e) => {
+				println!("{:?}: {}", level_path, e);
+				is_valid = false;
+				continue;
+			},
+		};
+
+		let mut diagnostics = validate(&level);
+		diagnostics.extend(validate_surfaces(&level, &movement));
+		diagnostics.extend(validate_references(&level, &transition_list, resource_path));
+
+		if diagnostics.is_empty() {
+			println!("{:?}: ok", level_path);
+		}
+
+		for diagnostic in diagnostics.iter() {
+			println!("{:?}: {}", level_path, diagnostic);
+			is_valid = false;
+		}
+	}
+
+	is_valid
+}
+
+/// Checks what the level refers to outside of itself: its wall tileset
+/// and the transitions its exits lead to.
+fn validate_references(level: &Level, transition_list: &TransitionList, resource_path: &path::Path) -> Vec<Diagnostic> {
+	let mut diagnostics = Vec::new();
+
+	if !resource_path.join("images").join("walls").join(&level.key).is_dir() {
+		diagnostics.push(Diagnostic::UnknownTileset { key: level.key.clone() });
+	}
+
+	for exit in level.exits.iter() {
+		if !transition_list.transitions.iter().any(|transition| transition.name == exit.transition) {
+			diagnostics.push(Diagnostic::UnknownExitTransition {
+				x: exit.x,
+				y: exit.y,
+				transition: exit.transition.clone(),
+			});
+		}
+	}
+
+	diagnostics
+}
+
+fn get_wall(level: &Level, x: f32, y: f32) -> Option<Wall> {
+	if x < 0.0 || y < 0.0 {
+		return None;
+	}
+
+	let (x, y) = (x as usize, y as usize);
+
+	if x < level.width && y < level.height {
+		Some(level.get(x, y))
+	}
+	else {
+		None
+	}
+}
+
+fn is_complete_door(level: &Level, x: usize, y: usize, part: DoorPart) -> bool {
+	let (start_x, start_y) = if part.horizontal {
+		(x as isize - part.index as isize, y as isize)
+	}
+	else {
+		(x as isize, y as isize - part.index as isize)
+	};
+
+	for index in 0..part.length {
+		let (x, y) = if part.horizontal {
+			(start_x + index as isize, start_y)
+		}
+		else {
+			(start_x, start_y + index as isize)
+		};
+
+		if x < 0 || y < 0 || x >= level.width as isize || y >= level.height as isize {
+			return false;
+		}
+
+		let expected = Wall::from_door_part(DoorPart { index, ..part });
+
+		if Some(level.get(x as usize, y as usize)) != expected {
+			return false;
+		}
+	}
+
+	true
+}
+
+fn validate_reachability(level: &Level, diagnostics: &mut Vec<Diagnostic>) {
+	let reachability = pathfinding::check_reachability(level);
+
+	let is_short_of_shards = diagnostics.iter().any(|diagnostic| match diagnostic {
+		Diagnostic::NotEnoughShards { .. } => true,
+		_ => false,
+	});
+
+	if !reachability.has_enough_shards() && !is_short_of_shards {
+		diagnostics.push(Diagnostic::NotEnoughReachableShards {
+			required: reachability.required_shards,
+			reachable: reachability.reachable_shards,
+		});
+	}
+
+	if !level.walls.iter().any(|wall| wall.is_door()) {
+		diagnostics.push(Diagnostic::NoDoor);
+	}
+	else if !reachability.is_door_reachable {
+		diagnostics.push(Diagnostic::UnreachableDoor);
+	}
+
+	for (index, entity) in level.entities.iter().enumerate() {
+		if !reachability.reachable_entities[index] {
+			diagnostics.push(Diagnostic::UnreachableEntity { index, x: entity.x, y: entity.y });
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::movement::{Surface, SurfaceArea};
+	use crate::resources::{Entity, EntityType, Exit, TileVariant};
+
+	/// A room walled all around, with the player in its top left corner,
+	/// a shard in the middle and a vertical door in the right wall.
+	///
+	/// ```text
+	/// ########
+	/// #@.....|
+	/// #..*...|
+	/// #......#
+	/// ########
+	/// ```
+	fn room() -> Level {
+		let mut level = Level::new(8, 5, "test");
+
+		for x in 0..8 {
+			level.set(x, 0, Wall::S);
+			level.set(x, 4, Wall::S);
+		}
+
+		for y in 0..5 {
+			level.set(0, y, Wall::S);
+			level.set(7, y, Wall::S);
+		}
+
+		level.set(7, 1, Wall::B0V);
+		level.set(7, 2, Wall::B1V);
+
+		level.player_x = 1.5;
+		level.player_y = 1.5;
+
+		level.entities.push(entity(3.5, 2.5, PickUpEffect::ActivateDoors));
+		level.shards_for_door_activation = 1;
+
+		level
+	}
+
+	fn entity(x: f32, y: f32, effect: PickUpEffect) -> Entity {
+		Entity {
+			entity_type: EntityType::Shard0,
+			x,
+			y,
+			light_radius: 0.0,
+			effect,
+		}
+	}
+
+	fn transitions(names: &[&str]) -> TransitionList {
+		let content: String = names.iter()
+			.map(|name| format!("[[transitions]]\nname = \"{}\"\ntransition_type = \"ToLevel\"\n", name))
+			.collect();
+
+		TransitionList::parse(&content).unwrap()
+	}
+
+	#[test]
+	fn room_is_valid() {
+		assert_eq!(validate(&room()), vec![]);
+	}
+
+	#[test]
+	fn player_outside_grid() {
+		let mut level = room();
+		level.player_x = 9.0;
+
+		// nothing is reachable from outside, but that isn't repeated
+		assert_eq!(validate(&level), vec![Diagnostic::PlayerOutsideGrid { x: 9.0, y: 1.5 }]);
+	}
+
+	#[test]
+	fn player_inside_wall() {
+		let mut level = room();
+		level.player_x = 0.5;
+
+		assert_eq!(validate(&level), vec![Diagnostic::PlayerInsideWall { x: 0.5, y: 1.5 }]);
+	}
+
+	#[test]
+	fn entity_outside_grid() {
+		let mut level = room();
+		level.entities.push(entity(-1.0, 2.5, PickUpEffect::IncreasePlayerLightRadius));
+
+		assert!(validate(&level).contains(&Diagnostic::EntityOutsideGrid { index: 1, x: -1.0, y: 2.5 }));
+	}
+
+	#[test]
+	fn entity_inside_wall() {
+		let mut level = room();
+		level.entities.push(entity(2.5, 0.5, PickUpEffect::IncreasePlayerLightRadius));
+
+		assert!(validate(&level).contains(&Diagnostic::EntityInsideWall { index: 1, x: 2.5, y: 0.5 }));
+	}
+
+	#[test]
+	fn not_enough_shards() {
+		let mut level = room();
+		level.shards_for_door_activation = 2;
+
+		// missing shards aren't reported a second time as unreachable
+		assert_eq!(validate(&level), vec![Diagnostic::NotEnoughShards { required: 2, available: 1 }]);
+	}
+
+	#[test]
+	fn walled_off_shard() {
+		let mut level = room();
+		level.entities[0].x = 5.5;
+		level.entities[0].y = 3.5;
+		level.set(4, 3, Wall::S);
+		level.set(5, 2, Wall::S);
+		level.set(6, 3, Wall::S);
+
+		assert_eq!(validate(&level), vec![
+			Diagnostic::NotEnoughReachableShards { required: 1, reachable: 0 },
+			Diagnostic::UnreachableEntity { index: 0, x: 5.5, y: 3.5 },
+		]);
+	}
+
+	#[test]
+	fn malformed_door() {
+		let mut level = room();
+		level.set(3, 0, Wall::B0H);
+
+		assert_eq!(validate(&level), vec![Diagnostic::MalformedDoor { x: 3, y: 0, wall: Wall::B0H }]);
+	}
+
+	#[test]
+	fn no_door() {
+		let mut level = room();
+		level.set(7, 1, Wall::S);
+		level.set(7, 2, Wall::S);
+
+		assert_eq!(validate(&level), vec![Diagnostic::NoDoor]);
+	}
+
+	#[test]
+	fn unreachable_door() {
+		let mut level = room();
+		level.set(6, 1, Wall::S);
+		level.set(6, 2, Wall::S);
+
+		assert_eq!(validate(&level), vec![Diagnostic::UnreachableDoor]);
+	}
+
+	#[test]
+	fn invalid_tile_variant() {
+		let mut level = room();
+		level.tile_variants.push(TileVariant { x: 8, y: 0, side: None, corner: None });
+		level.tile_variants.push(TileVariant { x: 1, y: 0, side: Some(resources::TILE_COUNT), corner: None });
+		level.tile_variants.push(TileVariant { x: 2, y: 0, side: None, corner: Some(resources::CORNER_COUNT) });
+		level.tile_variants.push(TileVariant { x: 3, y: 0, side: Some(0), corner: Some(0) });
+
+		assert_eq!(validate(&level), vec![
+			Diagnostic::InvalidTileVariant { x: 8, y: 0 },
+			Diagnostic::InvalidTileVariant { x: 1, y: 0 },
+			Diagnostic::InvalidTileVariant { x: 2, y: 0 },
+		]);
+	}
+
+	#[test]
+	fn invalid_corner_tolerance() {
+		let mut level = room();
+		level.corner_tolerance = PLAYER_WIDTH.min(PLAYER_HEIGHT);
+		assert_eq!(validate(&level), vec![]);
+
+		level.corner_tolerance = -1.0;
+		assert_eq!(validate(&level), vec![Diagnostic::InvalidCornerTolerance { tolerance: -1.0 }]);
+
+		level.corner_tolerance = PLAYER_WIDTH.min(PLAYER_HEIGHT) + 1.0;
+		assert_eq!(validate(&level), vec![Diagnostic::InvalidCornerTolerance { tolerance: level.corner_tolerance }]);
+	}
+
+	#[test]
+	fn unknown_surface() {
+		let mut movement = MovementConfig::default();
+		movement.surfaces.insert("ice".to_owned(), Surface::default());
+
+		let mut level = room();
+		level.surfaces.push(SurfaceArea { surface: "ice".to_owned(), x: 1, y: 1, width: 2, height: 2 });
+		level.surfaces.push(SurfaceArea { surface: "mud".to_owned(), x: 4, y: 2, width: 1, height: 1 });
+
+		assert_eq!(validate_surfaces(&level, &movement), vec![
+			Diagnostic::UnknownSurface { x: 4, y: 2, surface: "mud".to_owned() },
+		]);
+	}
+
+	#[test]
+	fn exit_without_door() {
+		let mut level = room();
+		level.exits.push(Exit { x: 7, y: 2, transition: "next".to_owned() });
+		level.exits.push(Exit { x: 1, y: 1, transition: "next".to_owned() });
+
+		assert_eq!(validate(&level), vec![Diagnostic::ExitWithoutDoor { x: 1, y: 1 }]);
+	}
+
+	#[test]
+	fn unknown_references() {
+		let mut level = room();
+		level.key = "missing".to_owned();
+		level.exits.push(Exit { x: 7, y: 1, transition: "next".to_owned() });
+		level.exits.push(Exit { x: 7, y: 2, transition: "missing".to_owned() });
+
+		let diagnostics = validate_references(&level, &transitions(&["next"]), path::Path::new("missing"));
+
+		assert_eq!(diagnostics, vec![
+			Diagnostic::UnknownTileset { key: "missing".to_owned() },
+			Diagnostic::UnknownExitTransition { x: 7, y: 2, transition: "missing".to_owned() },
+		]);
+	}
+}
@@ -0,0 +1,340 @@
+//! Light visibility over the level grid.
+//!
+//! Every ray is walked through the grid cell by cell (Amanatides & Woo),
+//! so it can't skip over thin corners the way fixed-step marching did,
+//! and the cost of a ray only depends on the number of cells it crosses.
+use crate::autotile;
+use crate::lighting::{SegmentLight, TileLightTracing};
+use crate::resources::Level;
+use crate::types::{Point2, Vector2};
+use ggez::graphics::Color;
+use std::collections::HashMap;
+
+pub const RAY_COUNT: usize = 360;
+
+/// Anything that emits light: the player, shards or static level lights.
+/// Position is relative to the level's top left corner.
+#[derive(Clone, Debug)]
+pub struct LightSource {
+	pub position: Point2,
+	pub radius: f32,
+	pub color: Color,
+}
+
+impl LightSource {
+	pub fn new(position: Point2, radius: f32, color: Color) -> Self {
+		Self {
+			position,
+			radius,
+			color,
+		}
+	}
+}
+
+/// Hit points are pushed this far past the cell border, so they end up
+/// inside the tile that was hit and not on the edge of its neighbour.
+const HIT_EPSILON: f32 = 0.01;
+
+/// Traces every light and merges their hits, so a tile lit by several
+/// sources is returned only once.
+pub fn trace_lights(level: &Level, tile_size: f32, lights: &[LightSource], are_doors_activated: bool) -> Vec<TileLightTracing> {
+	let mut tiles = Vec::new();
+
+	for light in lights.iter() {
+		if light.radius <= 0.0 {
+			continue;
+		}
+
+		merge_tiles(&mut tiles, trace_light(level, tile_size, light, are_doors_activated));
+	}
+
+	tiles
+}
+
+/// Adds `other` to `tiles`, merging the tiles present in both.
+pub fn merge_tiles(tiles: &mut Vec<TileLightTracing>, other: Vec<TileLightTracing>) {
+	let mut indices: HashMap<usize, usize> = tiles.iter()
+		.enumerate()
+		.map(|(index, tile)| (tile.tile_id, index))
+		.collect();
+
+	for tile in other {
+		if let Some(&index) = indices.get(&tile.tile_id) {
+			tiles[index].merge(&tile);
+		}
+		else {
+			indices.insert(tile.tile_id, tiles.len());
+			tiles.push(tile);
+		}
+	}
+}
+
+/// Whether nothing blocks light on the way from `from` to `to`.
+pub fn is_visible(level: &Level, tile_size: f32, from: Point2, to: Point2, are_doors_activated: bool) -> bool {
+	let distance = (to - from).length();
+
+	if distance <= 0.0 {
+		return true;
+	}
+
+	trace_ray(level, tile_size, from, (to - from) / distance, distance, are_doors_activated).is_none()
+}
+
+/// Traces `RAY_COUNT` rays from the light up to its radius and returns all
+/// tiles that blocked light, with their segments hit and how bright they
+/// got. The returned rects are relative to the level's top left corner.
+pub fn trace_light(level: &Level, tile_size: f32, light: &LightSource, are_doors_activated: bool) -> Vec<TileLightTracing> {
+	let origin = light.position;
+	let radius = light.radius;
+
+	let mut tiles = Vec::new();
+	let mut indices = HashMap::new();
+
+	for ray_n in 0..RAY_COUNT {
+		let angle = (ray_n as f32 * 360.0 / RAY_COUNT as f32).to_radians();
+		let direction = Vector2::new(angle.cos(), angle.sin());
+
+		if let Some((tile_id, distance)) = trace_ray(level, tile_size, origin, direction, radius, are_doors_activated) {
+			let index = *indices.entry(tile_id).or_insert_with(|| {
+				let x = (tile_id % level.width) as f32;
+				let y = (tile_id / level.width) as f32;
+				let position = Point2::new(
+					x * tile_size + tile_size / 2.0,
+					y * tile_size + tile_size / 2.0,
+				);
+
+				let mut tile = TileLightTracing::new(tile_id, position, tile_size, tile_size);
+				tile.neighbors = autotile::open_neighbors(level, tile_id % level.width, tile_id / level.width, are_doors_activated);
+
+				tiles.push(tile);
+				tiles.len() - 1
+			});
+
+			let intensity = SegmentLight::falloff(distance, radius);
+
+			tiles[index].register_hit(origin + direction * (distance + HIT_EPSILON), intensity, light.color);
+		}
+	}
+
+	tiles
+}
+
+/// Walks a single ray through the grid and returns the first tile that
+/// blocks light together with the distance to it.
+pub fn trace_ray(level: &Level, tile_size: f32, origin: Point2, direction: Vector2, radius: f32, are_doors_activated: bool) -> Option<(usize, f32)> {
+	let mut cell_x = (origin.x / tile_size).floor() as isize;
+	let mut cell_y = (origin.y / tile_size).floor() as isize;
+
+	let (step_x, mut t_max_x, t_delta_x) = axis_setup(origin.x, direction.x, cell_x, tile_size);
+	let (step_y, mut t_max_y, t_delta_y) = axis_setup(origin.y, direction.y, cell_y, tile_size);
+
+	loop {
+		let distance;
+
+		if t_max_x < t_max_y {
+			distance = t_max_x;
+			cell_x += step_x;
+			t_max_x += t_delta_x;
+		}
+		else {
+			distance = t_max_y;
+			cell_y += step_y;
+			t_max_y += t_delta_y;
+		}
+
+		if distance > radius {
+			return None;
+		}
+
+		if cell_x < 0 || cell_y < 0 || cell_x >= level.width as isize || cell_y >= level.height as isize {
+			continue;
+		}
+
+		let tile_id = cell_y as usize * level.width + cell_x as usize;
+
+		if blocks_light(level, tile_id, are_doors_activated) {
+			return Some((tile_id, distance));
+		}
+	}
+}
+
+pub fn blocks_light(level: &Level, tile_id: usize, are_doors_activated: bool) -> bool {
+	let wall = &level.walls[tile_id];
+
+	wall.is_wall() || (wall.is_door() && !are_doors_activated)
+}
+
+/// Returns step direction, distance to the first cell border and distance
+/// between borders along a single axis.
+fn axis_setup(origin: f32, direction: f32, cell: isize, tile_size: f32) -> (isize, f32, f32) {
+	if direction > 0.0 {
+		let border = (cell + 1) as f32 * tile_size;
+
+		(1, (border - origin) / direction, tile_size / direction)
+	}
+	else if direction < 0.0 {
+		let border = cell as f32 * tile_size;
+
+		(-1, (border - origin) / direction, tile_size / -direction)
+	}
+	else {
+		(0, std::f32::INFINITY, std::f32::INFINITY)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::resources::Wall;
+
+	const TILE_SIZE: f32 = 32.0;
+
+	/// Open level with a single wall at (2, 1), `from` sits on a 45° line
+	/// that clips only the bottom left corner of that wall.
+	fn corner_level() -> (Level, Point2) {
+		let mut level = Level::new(5, 5, "test");
+		level.set(2, 1, Wall::S);
+
+		(level, Point2::new(16.0, 15.5))
+	}
+
+	#[test]
+	fn ray_grazing_corner_hits_tile() {
+		let (level, origin) = corner_level();
+		let direction = Vector2::new(1.0, 1.0).normalize();
+
+		let (tile_id, distance) = trace_ray(&level, TILE_SIZE, origin, direction, 128.0, false)
+			.expect("Ray went through the corner");
+
+		// the wall at (2, 1)
+		assert_eq!(tile_id, level.width + 2);
+		assert!((distance - 48.0 * 2f32.sqrt()).abs() < 0.01, "distance {}", distance);
+	}
+
+	#[test]
+	fn grazing_hit_lights_corner_segment() {
+		let (level, origin) = corner_level();
+		let light = LightSource::new(origin, 128.0, Color::new(1.0, 1.0, 1.0, 1.0));
+
+		let tiles = trace_light(&level, TILE_SIZE, &light, false);
+		let tile = tiles.iter().find(|tile| tile.tile_id == level.width + 2)
+			.expect("Wall wasn't lit");
+
+		// segments go clockwise from the top left one, 6 is the bottom left
+		assert!(tile.segment_hits[6] > 0);
+		assert!(tile.get_lit_mask() & autotile::DOWN_LEFT != 0);
+	}
+
+	#[test]
+	fn ray_stops_at_radius() {
+		let (level, origin) = corner_level();
+		let direction = Vector2::new(1.0, 1.0).normalize();
+
+		assert_eq!(trace_ray(&level, TILE_SIZE, origin, direction, 60.0, false), None);
+	}
+
+	#[test]
+	fn walls_hide_points_behind_them() {
+		let (level, _) = corner_level();
+
+		assert!(is_visible(&level, TILE_SIZE, Point2::new(16.0, 48.0), Point2::new(48.0, 48.0), false));
+		assert!(!is_visible(&level, TILE_SIZE, Point2::new(48.0, 48.0), Point2::new(112.0, 48.0), false));
+		assert!(is_visible(&level, TILE_SIZE, Point2::new(48.0, 48.0), Point2::new(48.0, 48.0), false));
+	}
+
+	#[test]
+	fn doors_block_light_until_activated() {
+		let mut level = Level::new(5, 1, "test");
+		level.set(3, 0, Wall::B0V);
+		let origin = Point2::new(16.0, 16.0);
+		let direction = Vector2::new(1.0, 0.0);
+
+		assert_eq!(trace_ray(&level, TILE_SIZE, origin, direction, 256.0, false).map(|(id, _)| id), Some(3));
+		assert_eq!(trace_ray(&level, TILE_SIZE, origin, direction, 256.0, true), None);
+	}
+}
+
+/// Compares grid walking with the fixed-step marcher it replaced, run with
+/// `cargo +nightly bench --features bench`.
+#[cfg(all(test, feature = "bench"))]
+mod benches {
+	use super::*;
+	use crate::generator::{self, Algorithm, GeneratorOptions};
+	use crate::types::Rect;
+	use test::Bencher;
+
+	const TILE_SIZE: f32 = 32.0;
+	const STEP_DISTANCE: f32 = 8.0;
+
+	fn large_level() -> (Level, LightSource) {
+		let options = GeneratorOptions::new(Algorithm::RoomsAndCorridors, 45, 201, 201, "test");
+		let level = generator::generate(&options);
+
+		let position = Point2::new(level.player_x * TILE_SIZE, level.player_y * TILE_SIZE);
+		let light = LightSource::new(position, 256.0, Color::new(1.0, 1.0, 1.0, 1.0));
+
+		(level, light)
+	}
+
+	/// The old approach: collect every blocking tile in the radius, then
+	/// step along each ray and look for a tile containing the point.
+	fn march_light(level: &Level, light: &LightSource) -> usize {
+		let mut tiles = Vec::new();
+
+		for y in 0..level.height {
+			for x in 0..level.width {
+				let tile_id = y * level.width + x;
+
+				if !blocks_light(level, tile_id, false) {
+					continue;
+				}
+
+				let rect = Rect::new(x as f32 * TILE_SIZE, y as f32 * TILE_SIZE, TILE_SIZE, TILE_SIZE);
+				let center = Point2::new(rect.x + TILE_SIZE / 2.0, rect.y + TILE_SIZE / 2.0);
+
+				if (center - light.position).length() <= light.radius + TILE_SIZE {
+					tiles.push((tile_id, rect));
+				}
+			}
+		}
+
+		let mut hits = 0;
+
+		for ray_n in 0..RAY_COUNT {
+			let angle = (ray_n as f32 * 360.0 / RAY_COUNT as f32).to_radians();
+			let direction = Vector2::new(angle.cos(), angle.sin());
+
+			let mut step_n = 1;
+			loop {
+				let offset = direction * STEP_DISTANCE * step_n as f32;
+				if offset.length() > light.radius {
+					break;
+				}
+
+				let point = light.position + offset;
+				if tiles.iter().any(|(_, rect)| rect.contains(point)) {
+					hits += 1;
+					break;
+				}
+
+				step_n += 1;
+			}
+		}
+
+		hits
+	}
+
+	#[bench]
+	fn fixed_step_marcher(b: &mut Bencher) {
+		let (level, light) = large_level();
+
+		b.iter(|| march_light(&level, &light));
+	}
+
+	#[bench]
+	fn grid_walk(b: &mut Bencher) {
+		let (level, light) = large_level();
+
+		b.iter(|| trace_light(&level, TILE_SIZE, &light, false));
+	}
+}
@@ -1,10 +1,14 @@
 use crate::resources::{TransitionList, TransitionType};
+use crate::ghost::Ghosts;
+use crate::movement::MovementConfig;
+use crate::save::SaveGame;
 use crate::scenes::labyrinth::LabyrinthScene;
 use crate::scenes::transition::TransitionScene;
 use crate::scenes;
 use crate::types::Point2;
 use crate::{components, resources, input};
 use ggez::audio;
+use ggez::timer;
 use ggez::{Context};
 use ggez_goodies::scene;
 use specs::prelude::*;
@@ -18,7 +22,20 @@ pub struct World {
 	pub specs_world: specs::World,
 	pub exit: bool,
 	pub dimensions: (f32, f32),
-	pub transition_list: TransitionList,
+	pub resource_path: path::PathBuf,
+	pub transition_list: warmy::Res<TransitionList>,
+	/// Position in `transition_list`. The name is what counts, the index
+	/// is only a hint, so editing `transitions.toml` doesn't lose our place.
+	pub current_transition: (usize, String),
+	/// Overrides the seeds of all levels, see `LevelConfiguration::new`.
+	pub seed: Option<u64>,
+	pub movement: MovementConfig,
+	pub save: SaveGame,
+	pub ghosts: Ghosts,
+	/// Seconds every scene update advances by.
+	pub tick: f32,
+	/// Progress isn't saved while replaying someone else's run.
+	pub is_replay: bool,
 
 	pub sound_door: audio::Source,
 	pub sound_pick_up: audio::Source,
@@ -26,13 +43,22 @@ pub struct World {
 }
 
 impl World {
-	pub fn new(context: &mut Context, resource_path: &path::Path, transition_list: TransitionList, dimensions: (f32, f32)) -> Self {
+	pub fn new(context: &mut Context, resource_path: &path::Path, dimensions: (f32, f32), tick: f32, seed: Option<u64>) -> Self {
 		// TODO: There are potential problems.
 		// See https://github.com/ggez/game-template/blob/master/src/world.rs
 		let opt = warmy::StoreOpt::default().set_root(resource_path);
-		let store = warmy::Store::new(opt)
+		let mut store = warmy::Store::new(opt)
 			.expect("Could not create asset store? Does the directory exist?");
 
+		let transition_list = store
+			.get::<TransitionList>(&resources::ResourceKey::from_path("/transitions.toml"), context)
+			.expect("Couldn't load transition list!");
+		let current_transition = (0, transition_list.borrow().transitions[0].name.clone());
+		let movement = MovementConfig::load(context, "/movement.toml")
+			.expect("Couldn't load movement config!");
+		let save = SaveGame::load(context);
+		let ghosts = Ghosts::load(context);
+
 		let sound_door = audio::Source::new(context, "/audio/door.wav")
 			.expect("Count not load door sound!");
 		let sound_pick_up = audio::Source::new(context, "/audio/pick-up.wav")
@@ -49,7 +75,15 @@ impl World {
 			specs_world,
 			exit: false,
 			dimensions,
+			resource_path: resource_path.to_owned(),
 			transition_list,
+			current_transition,
+			seed,
+			movement,
+			save,
+			ghosts,
+			tick,
+			is_replay: false,
 
 			sound_door,
 			sound_pick_up,
@@ -63,45 +97,177 @@ impl World {
 		Point2::new(self.dimensions.0 / 2.0, self.dimensions.1 / 2.0)
 	}
 
+	/// How far drawing is between the last update and the next one,
+	/// from 0 to 1.
+	pub fn interpolation(&self, context: &mut ggez::Context) -> f32 {
+		let remaining = timer::duration_to_f64(timer::remaining_update_time(context)) as f32;
+
+		(remaining / self.tick).min(1.0)
+	}
+
+	/// Index of the current transition, looked up by name if the list
+	/// changed since.
+	pub fn current_n(&self) -> usize {
+		let (hint, name) = &self.current_transition;
+
+		match self.find_transition(*hint, name) {
+			Some(n) => n,
+			None => {
+				warn!("Transition {} is gone, staying at #{}.", name, hint);
+
+				(*hint).min(self.transition_list.borrow().transitions.len() - 1)
+			},
+		}
+	}
+
+	/// Index of the transition called `name`, `hint` is checked first.
+	fn find_transition(&self, hint: usize, name: &str) -> Option<usize> {
+		let transitions = &self.transition_list.borrow().transitions;
+
+		if transitions.get(hint).map(|transition| transition.name.as_str()) == Some(name) {
+			return Some(hint);
+		}
+
+		transitions.iter().position(|transition| transition.name == name)
+	}
+
+	fn set_current_n(&mut self, n: usize) {
+		let name = self.transition_list.borrow().transitions[n].name.clone();
+
+		self.current_transition = (n, name);
+	}
+
 	pub fn initial_scene(&mut self, context: &mut ggez::Context) -> TransitionScene {
-		let current = self.transition_list.transitions[0].clone();
+		let current = self.transition_list.borrow().transitions[0].clone();
 		let is_main = true;
 
 		let scene = match current.transition_type {
 			TransitionType::ToScreen => {
 				TransitionScene::new(self, context, is_main, &current.name)
 			},
-			TransitionType::ToLevel => panic!("First level expected to be a screen!"),
+			TransitionType::ToLevel | TransitionType::ToGeneratedLevel => panic!("First level expected to be a screen!"),
 		};
 
 		scene
 	}
 
 	pub fn next_scene(&mut self, context: &mut ggez::Context) -> scenes::Switch {
-		if self.transition_list.current_n + 1 == self.transition_list.transitions.len() {
+		let current_n = self.current_n();
+
+		if current_n + 1 == self.transition_list.borrow().transitions.len() {
+			// finished, nothing left to continue
+			self.save.transition = None;
+			self.save_progress(context);
 			self.reset_scenes();
 
 			return scene::SceneSwitch::Pop;
 		}
 
-		let is_main = self.transition_list.current_n == 0;
+		self.switch_to(context, current_n + 1, current_n == 0)
+	}
+
+	/// Like `next_scene`, but goes on with the transition called `name`,
+	/// for levels with several exits.
+	pub fn exit_to(&mut self, context: &mut ggez::Context, name: &str) -> scenes::Switch {
+		let current_n = self.current_n();
+
+		match self.find_transition(current_n + 1, name) {
+			Some(n) if n > 0 => self.switch_to(context, n, current_n == 0),
+			_ => {
+				error!("Exit leads to unknown transition {}, going on with the next one.", name);
+
+				self.next_scene(context)
+			},
+		}
+	}
+
+	/// Resumes at the saved transition from the main screen, or starts
+	/// from the beginning if the save refers to a transition that's gone.
+	pub fn continue_scene(&mut self, context: &mut ggez::Context) -> scenes::Switch {
+		let saved = self.save.transition.clone()
+			.map(|name| (self.find_transition(self.save.transition_n, &name), name));
+
+		match saved {
+			// the main screen itself, there's nothing to skip
+			Some((Some(0), _)) => self.next_scene(context),
+			Some((Some(n), _)) => self.switch_to(context, n, true),
+			Some((None, name)) => {
+				warn!("Saved transition {} doesn't exist anymore, starting from the beginning.", name);
+
+				self.next_scene(context)
+			},
+			None => self.next_scene(context),
+		}
+	}
+
+	/// Adds the current level to the stats, written with the next save.
+	pub fn record_level(&mut self, time: f32, shards: usize) {
+		let name = self.current_transition.1.clone();
+
+		self.save.record_level(&name, time, shards);
+	}
+
+	pub fn save_progress(&mut self, context: &ggez::Context) {
+		if self.is_replay {
+			return;
+		}
+
+		if let Err(e) = self.save.write(context) {
+			warn!("Couldn't save progress: {}", e);
+		}
+	}
 
-		self.transition_list.current_n += 1;
-		let current = self.transition_list.transitions[self.transition_list.current_n].clone();
+	pub fn save_ghosts(&mut self, context: &ggez::Context) {
+		if self.is_replay {
+			return;
+		}
+
+		if let Err(e) = self.ghosts.write(context) {
+			warn!("Couldn't save ghosts: {}", e);
+		}
+	}
+
+	fn switch_to(&mut self, context: &mut ggez::Context, n: usize, is_main: bool) -> scenes::Switch {
+		self.set_current_n(n);
+		let current = self.transition_list.borrow().transitions[n].clone();
 
 		let switch = match current.transition_type {
-			TransitionType::ToLevel => {
-				let scene = LabyrinthScene::new(self, context, &current.name);
+			TransitionType::ToLevel | TransitionType::ToGeneratedLevel => {
+				let scene = match (&current.transition_type, &current.generator) {
+					(TransitionType::ToGeneratedLevel, Some(options)) => LabyrinthScene::generated(self, context, options),
+					(TransitionType::ToGeneratedLevel, None) => Err(ggez::GameError::ResourceLoadError(
+						"Generated level transition has no generator options!".to_owned()
+					)),
+					_ => LabyrinthScene::new(self, context, &current.name),
+				};
 
-				if is_main {
-					scene::SceneSwitch::push(scene)
-				}
-				else {
-					scene::SceneSwitch::replace(scene)
+				match scene {
+					Ok(scene) => {
+						self.save_transition(context, n, &current.name);
+
+						if is_main {
+							scene::SceneSwitch::push(scene)
+						}
+						else {
+							scene::SceneSwitch::replace(scene)
+						}
+					},
+					Err(e) => {
+						error!("Couldn't load level {}: {}", current.name, e);
+						self.reset_scenes();
+
+						if is_main {
+							scene::SceneSwitch::None
+						}
+						else {
+							scene::SceneSwitch::Pop
+						}
+					},
 				}
 			},
 			TransitionType::ToScreen => {
 				let scene = TransitionScene::new(self, context, is_main, &current.name);
+				self.save_transition(context, n, &current.name);
 
 				if is_main {
 					scene::SceneSwitch::push(scene)
@@ -115,7 +281,15 @@ impl World {
 		switch
 	}
 
+	/// Only called once the transition is loaded, so continuing never
+	/// leads to one that can't be shown.
+	fn save_transition(&mut self, context: &ggez::Context, n: usize, name: &str) {
+		self.save.transition = Some(name.to_owned());
+		self.save.transition_n = n;
+		self.save_progress(context);
+	}
+
 	pub fn reset_scenes(&mut self) {
-		self.transition_list.current_n = 0;
+		self.set_current_n(0);
 	}
 }